@@ -0,0 +1,329 @@
+use std::time::{Duration, Instant};
+
+use cgmath::Point3;
+use winit::{
+    event::*,
+    event_loop::ControlFlow,
+    window::Window
+};
+use winit_input_helper::WinitInputHelper;
+
+use crate::game::{self, BindGroupSource};
+use crate::player::Player;
+use crate::terrain::{self, ChunkShape};
+use crate::workers::WorkerPool;
+
+use block_mesh::ndshape::ConstShape;
+
+/// How many times a second the world is simulated, no matter the frame rate
+pub const TICKS_PER_SECOND: f32 = 60.;
+const TICK_LENGTH: f32 = 1. / TICKS_PER_SECOND;
+/// Most time to catch up on in one frame, so a long freeze doesn't get followed by a burst of ticks
+const MAX_FRAME_TIME: f32 = 0.25;
+
+/// Most finished meshes to upload per frame, so a lot finishing at once doesn't cause a stutter
+const MESH_UPLOADS_PER_FRAME: usize = 8;
+/// Most time to spend each frame adding loaded chunks to the world (lighting them isn't free),
+/// and getting changed chunks ready to remesh. At least one of each still happens every frame, so they can't stall.
+const CHUNK_INSERT_TIME: Duration = Duration::from_millis(3);
+const REMESH_TIME: Duration = Duration::from_millis(2);
+/// How many chunks up and down from the camera are loaded (the view distance is how far they go sideways)
+const VERTICAL_VIEW_DISTANCE: i32 = 2;
+/// How far away blocks can be broken and placed
+const REACH: f32 = 8.;
+/// Where F2 saves screenshots
+const SCREENSHOT_DIR: &str = "screenshots";
+/// How fast [ and ] move the sun, in radians per second
+const SUN_SPEED: f32 = 0.5;
+
+/// The simulated part of the game, which moves forward in fixed ticks
+pub struct World {
+    pub terrain: terrain::TerrainState,
+    /// Walks around and collides with the terrain (F to fly)
+    pub player: Player,
+    /// Where the player was at the start of the last tick, to draw them in between ticks
+    prev_player_pos: Point3<f32>,
+}
+impl World {
+    pub fn new(terrain: terrain::TerrainState, player: Player) -> Self {
+        Self {
+            prev_player_pos: player.pos,
+            terrain,
+            player,
+        }
+    }
+
+    /// Moves the world forward by one tick (TICK_LENGTH seconds)
+    pub fn tick(&mut self, wish: cgmath::Vector3<f32>, jump: bool) {
+        self.prev_player_pos = self.player.pos;
+        self.player.update(&self.terrain, wish, jump, TICK_LENGTH);
+    }
+
+    /// Where the player's eye is `alpha` (0..1) of the way from the last tick to the next
+    pub fn interpolated_eye(&self, alpha: f32) -> Point3<f32> {
+        let pos = self.prev_player_pos + (self.player.pos - self.prev_player_pos) * alpha;
+        pos + (self.player.eye() - self.player.pos)
+    }
+}
+
+/// Owns everything: the window, the renderer, and the world
+pub struct Game {
+    window: Window,
+    ctx: game::WgpuCtx,
+    // has methods like .key_held(VirtualKeyCode::W)
+    input: WinitInputHelper,
+
+    camera: game::camera::CameraData,
+    camera_group: wgpu::BindGroup,
+    camera_buffer: wgpu::Buffer,
+    // The sun ([ and ] move it across the sky)
+    light: game::light::LightData,
+    light_group: wgpu::BindGroup,
+    light_buffer: wgpu::Buffer,
+    depth_texture: game::texture::Texture,
+    chunk_r: game::ChunkRender,
+    // Mouse look and WASD movement
+    controller: game::controller::CameraController,
+
+    pub world: World,
+    // Loads chunks around the camera as it moves
+    streamer: terrain::stream::ChunkStreamer,
+    // Generates and meshes chunks in the background
+    workers: WorkerPool,
+    // What right-click places, picked with the number keys
+    held_block: terrain::Block,
+    /// From the last frame (F3 prints them)
+    pub render_stats: game::RenderStats,
+
+    /// Time that hasn't been simulated yet, less than one tick after catching up
+    accumulator: f32,
+    last_frame: Instant,
+}
+impl Game {
+    /// `world_dir` is where the terrain's chunks get saved, which should be different for every generator and seed.
+    /// Chunks up to `view_distance` chunks away (horizontally) are loaded.
+    pub fn new(window: Window, terrain: terrain::TerrainState, world_dir: std::path::PathBuf, view_distance: i32) -> Result<Self, game::CtxError> {
+        let wsize = window.inner_size();
+        let ctx = pollster::block_on(game::WgpuCtx::default(&window))?;
+
+        let camera = game::camera::CameraData {
+            eye: cgmath::point3(0.,18.,-2.),
+            target: cgmath::point3(16.,0.,16.),
+            up: cgmath::vec3(0.,1.,0.),
+
+            aspect: wsize.width as f32 / wsize.height as f32,
+            fovy: 70.,
+            znear: 0.1,
+            zfar: 400.
+        };
+        let (camera_group, camera_buffer) = camera.bind_group(&ctx.device, &ctx.queue, &camera.bind_group_layout(&ctx.device));
+        let depth_texture = game::texture::Texture::create_depth_texture(&ctx.device, &ctx.config, "depth tex");
+        let light = game::light::LightData::default();
+        let (light_group, light_buffer) = light.bind_group(&ctx.device, &ctx.queue, &light.bind_group_layout(&ctx.device));
+        let atlas = game::texture::BlockAtlas::load(&ctx.device, &ctx.queue);
+        let chunk_r = game::ChunkRender::new(&ctx, &camera, &atlas, &light, ChunkShape::SIZE as usize);
+        let controller = game::controller::CameraController::new(&camera);
+
+        let workers = WorkerPool::with_available_threads(terrain.generator.clone(), world_dir.clone());
+        let world = World::new(terrain, Player::at_eye(camera.eye));
+
+        Ok(Self {
+            window,
+            ctx,
+            input: WinitInputHelper::new(),
+            camera,
+            camera_group,
+            camera_buffer,
+            light,
+            light_group,
+            light_buffer,
+            depth_texture,
+            chunk_r,
+            controller,
+            world,
+            streamer: terrain::stream::ChunkStreamer::new(world_dir, view_distance, VERTICAL_VIEW_DISTANCE),
+            workers,
+            held_block: terrain::Block::STONE,
+            render_stats: game::RenderStats::default(),
+            accumulator: 0.,
+            last_frame: Instant::now(),
+        })
+    }
+
+    pub fn handle_event(&mut self, main_event: &Event<()>, control_flow: &mut ControlFlow) {
+        // Input also checks for some special events, which is why we update only when it says so
+        if self.input.update(main_event) {
+            self.frame();
+
+            // The code renders on the RedrawRequested event, but normally that's only sent once, then on resizes.
+            //  this makes it send the RedrawRequested event every frame, as well.
+            self.window.request_redraw();
+        }
+
+        match main_event {
+            Event::WindowEvent {
+                window_id,
+                event: window_event
+            } if *window_id == self.window.id() => {
+                match window_event {
+                    // Close when you press the red button on the window
+                    WindowEvent::CloseRequested => {
+                        if let Err(e) = self.world.terrain.save_all(&self.streamer.world_dir) {
+                            eprintln!("Couldn't save the world: {}", e);
+                        }
+                        *control_flow = ControlFlow::Exit
+                    },
+                    // Let go of the mouse when switching to another window
+                    WindowEvent::Focused(false) if self.controller.grabbed => self.controller.set_grabbed(&self.window, false),
+                    // Resize correctly
+                    WindowEvent::Resized(new_size) => self.resize(*new_size),
+                    _ => {}
+                }
+            },
+            // Raw mouse movement, which still comes in when the cursor is grabbed
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta: (dx, dy) },
+                ..
+            } => self.controller.mouse_moved(*dx, *dy),
+            // Let the OS request us to re-render whenever it needs to
+            Event::RedrawRequested(window_id) if *window_id == self.window.id() => {
+                match self.chunk_r.render(&self.ctx, &self.depth_texture, &self.camera, &self.camera_group, &self.light_group, self.streamer.in_range()) {
+                    Ok(stats) => self.render_stats = stats,
+                    // Reconfigure the surface if lost
+                    Err(wgpu::SurfaceError::Lost) => self.resize(self.ctx.size),
+                    // The system is out of memory, we should probably quit
+                    Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+                    // All other errors (Outdated, Timeout) should be resolved by the next frame
+                    Err(e) => eprintln!("{:?}", e),
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        self.ctx.resize(new_size);
+        self.depth_texture = game::texture::Texture::create_depth_texture(&self.ctx.device, &self.ctx.config, "depth tex");
+        self.camera.aspect = self.ctx.config.width as f32 / self.ctx.config.height as f32;
+    }
+
+    /// Runs once per frame: handles input, runs as many ticks as have built up, and gets the next frame ready
+    fn frame(&mut self) {
+        let input = &self.input;
+        let now = Instant::now();
+        let frame_time = (now - self.last_frame).as_secs_f32().min(MAX_FRAME_TIME);
+        self.accumulator += frame_time;
+        self.last_frame = now;
+
+        // Looking around and one-off key presses happen every frame, so they don't lag or get missed between ticks
+        self.controller.update(input, &self.window);
+        if input.key_pressed(VirtualKeyCode::F2) {
+            self.screenshot();
+        }
+        if input.key_pressed(VirtualKeyCode::F3) {
+            let stats = &self.render_stats;
+            println!(
+                "{} chunks drawn, {} culled, {:.1} MB of chunk meshes, {:.1} MB of loaded terrain (blocks and light)",
+                stats.drawn, stats.culled, stats.mesh_bytes as f64 / 1e6, self.world.terrain.size_bytes() as f64 / 1e6
+            );
+        }
+        if input.key_pressed(VirtualKeyCode::F) {
+            self.world.player.flying = !self.world.player.flying;
+            self.controller.speed = if self.world.player.flying { 10. } else { 5. };
+        }
+        // Move the sun across the sky, over the top and back down the other side
+        let sun_turn = input.key_held(VirtualKeyCode::RBracket) as i32 - input.key_held(VirtualKeyCode::LBracket) as i32;
+        if sun_turn != 0 {
+            let limit = std::f32::consts::PI;
+            self.light.elevation = (self.light.elevation + sun_turn as f32 * SUN_SPEED * frame_time).clamp(0., limit);
+            self.light.update_bind_group(&self.light_buffer, &self.ctx.queue);
+        }
+
+        let wish = self.controller.movement(input);
+        let jump = input.key_held(VirtualKeyCode::Space);
+        while self.accumulator >= TICK_LENGTH {
+            self.world.tick(wish, jump);
+            self.accumulator -= TICK_LENGTH;
+        }
+
+        // The camera is the player's head, drawn between the last two ticks so movement is smooth at any frame rate
+        self.camera.eye = self.world.interpolated_eye(self.accumulator / TICK_LENGTH);
+        self.controller.look(&mut self.camera);
+        self.camera.update_bind_group(&self.camera_buffer, &self.ctx.queue);
+
+        self.edit_blocks();
+
+        let terrain = &mut self.world.terrain;
+        let workers = &self.workers;
+        for pos in self.streamer.update(terrain, self.camera.eye.into(), |pos| workers.load(pos)) {
+            self.chunk_r.unload(pos);
+        }
+        let deadline = Instant::now() + CHUNK_INSERT_TIME;
+        while let Some((pos, chunk)) = workers.try_recv_chunk() {
+            self.streamer.finish_loaded(terrain, pos, chunk);
+            if Instant::now() >= deadline { break }
+        }
+        self.chunk_r.remesh_dirty(terrain, workers, Instant::now() + REMESH_TIME);
+        self.chunk_r.upload_finished(&self.ctx, workers, MESH_UPLOADS_PER_FRAME);
+    }
+
+    /// Saves what's on screen to screenshots/<time>.png (F2)
+    fn screenshot(&self) {
+        let pixels = match self.chunk_r.screenshot(&self.ctx, &self.depth_texture, &self.camera, &self.camera_group, &self.light_group, self.streamer.in_range()) {
+            Ok(pixels) => pixels,
+            Err(e) => {
+                eprintln!("Couldn't take a screenshot: {}", e);
+                return;
+            }
+        };
+        let image = image::RgbaImage::from_raw(self.ctx.config.width, self.ctx.config.height, pixels).unwrap();
+
+        // Milliseconds since 1970, so they sort in the order they were taken
+        let time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis();
+        let path = std::path::Path::new(SCREENSHOT_DIR).join(format!("{}.png", time));
+        match std::fs::create_dir_all(SCREENSHOT_DIR).map_err(image::ImageError::from).and_then(|_| image.save(&path)) {
+            Ok(()) => println!("Saved a screenshot to {}", path.display()),
+            Err(e) => eprintln!("Couldn't save a screenshot: {}", e),
+        }
+    }
+
+    /// Break (left click) or place (right click) whatever's in the middle of the screen
+    fn edit_blocks(&mut self) {
+        let input = &self.input;
+        for (key, block) in [
+            (VirtualKeyCode::Key1, terrain::Block::STONE),
+            (VirtualKeyCode::Key2, terrain::Block::DIRT),
+            (VirtualKeyCode::Key3, terrain::Block::GRASS),
+            (VirtualKeyCode::Key4, terrain::Block::GLASS),
+            (VirtualKeyCode::Key5, terrain::Block::LAMP),
+        ] {
+            if input.key_pressed(key) {
+                self.held_block = block;
+                println!("Holding {}", block.ty().name);
+            }
+        }
+
+        if !self.controller.grabbed {
+            // The first click just grabs the mouse
+            if input.mouse_pressed(0) {
+                self.controller.set_grabbed(&self.window, true);
+            }
+            return;
+        }
+        if !(input.mouse_pressed(0) || input.mouse_pressed(1)) { return }
+
+        let world = &mut self.world;
+        let look = self.camera.target - self.camera.eye;
+        if let Some(hit) = world.terrain.raycast(self.camera.eye.into(), look.into(), REACH) {
+            if input.mouse_pressed(0) {
+                world.terrain.break_block(&hit);
+            } else {
+                // Don't get stuck in the block you just placed
+                let target = [0, 1, 2].map(|axis| hit.block[axis] + hit.normal[axis]);
+                if !world.player.overlaps_block(target) {
+                    world.terrain.place_block(&hit, self.held_block);
+                }
+            }
+        }
+    }
+}
@@ -32,6 +32,10 @@ impl CameraData {
 
         OPENGL_TO_WGPU_MATRIX * proj * view
     }
+    /// The space the camera can see, for skipping things that are off screen
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(&self.build_transform())
+    }
     pub fn uniform(&self) -> CameraUniform {
         CameraUniform {
             transform: self.build_transform().into()
@@ -39,6 +43,37 @@ impl CameraData {
     }
 }
 
+/// Six planes facing inwards (ax + by + cz + d >= 0 is inside)
+pub struct Frustum {
+    planes: [cgmath::Vector4<f32>; 6]
+}
+impl Frustum {
+    /// Pulls the planes out of a view-projection matrix (Gribb & Hartmann's method, for wgpu's 0..1 depth)
+    pub fn from_matrix(m: &cgmath::Matrix4<f32>) -> Self {
+        use cgmath::Matrix;
+        let row = |i: usize| m.row(i);
+        Self {
+            planes: [
+                row(3) + row(0), // left
+                row(3) - row(0), // right
+                row(3) + row(1), // bottom
+                row(3) - row(1), // top
+                row(2),          // near
+                row(3) - row(2), // far
+            ]
+        }
+    }
+
+    /// Whether any of the box might be visible. Can say yes for boxes just outside a corner, but never no for a visible one.
+    pub fn intersects_aabb(&self, min: [f32; 3], max: [f32; 3]) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane's normal; if that's outside, the whole box is
+            let corner = [0, 1, 2].map(|axis| if plane[axis] >= 0. { max[axis] } else { min[axis] });
+            plane.x * corner[0] + plane.y * corner[1] + plane.z * corner[2] + plane.w >= 0.
+        })
+    }
+}
+
 impl BindGroupSource<Buffer> for CameraData {
     fn bind_group_layout(&self, device: &Device) -> BindGroupLayout {
         device.create_bind_group_layout(&BindGroupLayoutDescriptor {
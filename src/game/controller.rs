@@ -0,0 +1,109 @@
+use cgmath::{InnerSpace, Vector3};
+use winit::event::VirtualKeyCode;
+use winit::window::Window;
+use winit_input_helper::WinitInputHelper;
+
+use super::camera::CameraData;
+
+/// First-person controls: mouse to look around, WASD to move, space/shift to go up/down
+pub struct CameraController {
+    /// Radians, 0 is looking along +x, increasing towards +z
+    pub yaw: f32,
+    /// Radians, positive is looking up
+    pub pitch: f32,
+    /// Blocks per second
+    pub speed: f32,
+    /// Radians per pixel of mouse movement
+    pub sensitivity: f32,
+    /// Whether the cursor is locked to the window (and hidden), so the mouse looks around
+    pub grabbed: bool,
+    /// Mouse movement since the last update()
+    mouse_delta: (f32, f32),
+}
+impl CameraController {
+    /// Starts out looking the same way the camera does
+    pub fn new(camera: &CameraData) -> Self {
+        let dir = (camera.target - camera.eye).normalize();
+        Self {
+            yaw: dir.z.atan2(dir.x),
+            pitch: dir.y.asin(),
+            speed: 5.,
+            sensitivity: 0.003,
+            grabbed: false,
+            mouse_delta: (0., 0.),
+        }
+    }
+
+    /// Which way the camera is looking
+    pub fn forward(&self) -> Vector3<f32> {
+        cgmath::vec3(
+            self.pitch.cos() * self.yaw.cos(),
+            self.pitch.sin(),
+            self.pitch.cos() * self.yaw.sin(),
+        )
+    }
+
+    /// Call with the raw mouse movement (DeviceEvent::MouseMotion), which keeps coming even when the cursor is grabbed
+    pub fn mouse_moved(&mut self, dx: f64, dy: f64) {
+        if self.grabbed {
+            self.mouse_delta.0 += dx as f32;
+            self.mouse_delta.1 += dy as f32;
+        }
+    }
+
+    pub fn set_grabbed(&mut self, window: &Window, grabbed: bool) {
+        // Not every platform can grab the cursor, but looking around still works without it
+        if let Err(e) = window.set_cursor_grab(grabbed) {
+            eprintln!("Couldn't grab the cursor: {}", e);
+        }
+        window.set_cursor_visible(!grabbed);
+        self.grabbed = grabbed;
+        self.mouse_delta = (0., 0.);
+    }
+
+    /// Turns with the mouse. Call once per update, before movement().
+    pub fn update(&mut self, input: &WinitInputHelper, window: &Window) {
+        if input.key_pressed(VirtualKeyCode::Escape) && self.grabbed {
+            self.set_grabbed(window, false);
+        }
+
+        let (dx, dy) = std::mem::take(&mut self.mouse_delta);
+        self.yaw += dx * self.sensitivity;
+        // Looking straight up or down makes the view matrix fall apart
+        let limit = std::f32::consts::FRAC_PI_2 - 0.01;
+        self.pitch = (self.pitch - dy * self.sensitivity).clamp(-limit, limit);
+    }
+
+    /// The velocity WASD (and space/shift, for going up and down) are asking for, in blocks per second
+    pub fn movement(&self, input: &WinitInputHelper) -> Vector3<f32> {
+        // Moving ignores pitch, so looking down doesn't make W slower
+        let forward = cgmath::vec3(self.yaw.cos(), 0., self.yaw.sin());
+        let right = cgmath::vec3(-self.yaw.sin(), 0., self.yaw.cos());
+
+        let mut movement = cgmath::vec3(0., 0., 0.);
+        for (key, dir) in [
+            (VirtualKeyCode::W, forward),
+            (VirtualKeyCode::S, -forward),
+            (VirtualKeyCode::D, right),
+            (VirtualKeyCode::A, -right),
+        ] {
+            if input.key_held(key) {
+                movement += dir;
+            }
+        }
+        // So going diagonally isn't faster
+        if movement.magnitude2() > 0. {
+            movement = movement.normalize() * self.speed;
+        }
+
+        // Kept separate from the horizontal movement, so jumping doesn't slow you down
+        if input.key_held(VirtualKeyCode::Space) { movement.y += self.speed }
+        if input.key_held(VirtualKeyCode::LShift) { movement.y -= self.speed }
+        movement
+    }
+
+    /// Points the camera the way the controller is looking
+    pub fn look(&self, camera: &mut CameraData) {
+        camera.target = camera.eye + self.forward();
+    }
+}
@@ -5,8 +5,47 @@ pub mod types {
     use super::util;
     use wgpu::*;
 
-    pub type Index = u16;
-    pub const INDEX_FORMAT: IndexFormat = IndexFormat::Uint16;
+    /// Meshes are built with 32-bit indices, and get uploaded as 16-bit ones when they have few enough vertices
+    pub type Index = u32;
+
+    /// The smallest index format that can point at every one of `num_verts` vertices
+    pub fn index_format_for(num_verts: usize) -> IndexFormat {
+        if num_verts <= u16::MAX as usize + 1 {
+            IndexFormat::Uint16
+        } else {
+            IndexFormat::Uint32
+        }
+    }
+
+    /// Smallest buffers upload() makes, so even empty meshes get real buffers
+    const MIN_VERTS: u32 = 64;
+    const MIN_INDXS: u32 = 96;
+    /// How many times bigger buffers get when a mesh outgrows them, so a growing mesh isn't reallocated every update
+    const GROWTH: u32 = 2;
+    /// Meshes that use less than 1/SHRINK_BELOW of their buffers get smaller ones (if the GPUMesh shrinks)
+    const SHRINK_BELOW: u32 = 4;
+
+    /// Bytes per index
+    fn index_size(format: IndexFormat) -> usize {
+        match format {
+            IndexFormat::Uint16 => 2,
+            IndexFormat::Uint32 => 4,
+        }
+    }
+
+    /// Indices as bytes in the given format, padded with zeros up to `len` indices
+    /// (and to a multiple of 4 bytes, which is what buffer writes have to be)
+    fn index_bytes(indxs: &[Index], format: IndexFormat, len: usize) -> Vec<u8> {
+        let align = COPY_BUFFER_ALIGNMENT as usize;
+        let padded_len = (len.max(indxs.len()) * index_size(format)).div_ceil(align) * align;
+        let mut bytes = Vec::with_capacity(padded_len);
+        match format {
+            IndexFormat::Uint16 => bytes.extend(indxs.iter().flat_map(|&i| (i as u16).to_ne_bytes())),
+            IndexFormat::Uint32 => bytes.extend(indxs.iter().flat_map(|&i| i.to_ne_bytes())),
+        }
+        bytes.resize(padded_len, 0);
+        bytes
+    }
 
     /// The data necessary to make a GPUMesh
     /// Meshes are generated on the CPU, then uploaded to the GPU
@@ -22,20 +61,28 @@ pub mod types {
         pub indxs: Vec<Index>,
     }
     impl<V: Clone + bytemuck::Pod> CPUMesh<V> {
+        /// Uint16 unless there are too many vertices for that
+        pub fn index_format(&self) -> IndexFormat {
+            index_format_for(self.verts.len())
+        }
+
+        /// Uploads into buffers with room for `max_verts` and `max_indxs` (or the whole mesh, if it's bigger)
         pub fn upload_sized(&self, device: &Device, max_verts: u32, max_indxs: u32) -> GPUMesh<V> {
+            let max_verts = max_verts.max(self.verts.len() as u32);
+            let max_indxs = max_indxs.max(self.indxs.len() as u32);
+
             // Pad verts with empty vertices if necessary (you can't make a buffer bigger than the data you put in it)
             let mut ext_verts = self.verts.clone();
             if max_verts > self.verts.len() as u32 {
                 ext_verts.extend(
-                    std::iter::repeat(V::zeroed()).take(max_verts as usize - ext_verts.len()),
+                    std::iter::repeat_n(V::zeroed(), max_verts as usize - ext_verts.len()),
                 );
             }
 
-            // Pad indxs as well
-            let mut ext_indxs = self.indxs.clone();
-            if max_indxs > self.indxs.len() as u32 {
-                ext_indxs.extend(std::iter::repeat(0).take(max_indxs as usize - ext_indxs.len()));
-            }
+            // Pad indxs as well. Their format only fits this mesh's vertices, not necessarily the whole buffer's worth;
+            // update_gpu_mesh() reallocates when a bigger mesh needs wider indices.
+            let index_format = self.index_format();
+            let ext_indxs = index_bytes(&self.indxs, index_format, max_indxs as usize);
 
             let vertbuf = util::fast_buffer(
                 device,
@@ -54,20 +101,49 @@ pub mod types {
                 indxbuf,
                 num_indxs: self.indxs.len() as u32,
                 max_indxs,
+                index_format,
+                shrink: false,
 
                 _phantom_vert_data: core::marker::PhantomData,
             }
         }
+        /// Uploads into buffers just big enough for the mesh (they grow if update_gpu_mesh() needs them to)
         pub fn upload(&self, device: &Device) -> GPUMesh<V> {
-            self.upload_sized(device, self.verts.len() as u32, self.indxs.len() as u32)
+            self.upload_sized(device, MIN_VERTS, MIN_INDXS)
         }
 
-        pub fn update_gpu_mesh(&self, gpu_mesh: &mut GPUMesh<V>, queue: &mut Queue) {
-            // TODO: using the max_verts & max_indxs fields, check if this CPUMesh has too many vertices or indices
+        /// Puts this mesh into a GPUMesh's buffers. If it doesn't fit, they get replaced by ones GROWTH times bigger than it needs;
+        /// if the GPUMesh shrinks and this uses a lot less than they have, by smaller ones.
+        pub fn update_gpu_mesh(&self, gpu_mesh: &mut GPUMesh<V>, device: &Device, queue: &Queue) {
+            let (verts, indxs) = (self.verts.len() as u32, self.indxs.len() as u32);
+            let fits = verts <= gpu_mesh.max_verts
+                && indxs <= gpu_mesh.max_indxs
+                && (gpu_mesh.index_format == IndexFormat::Uint32 || self.index_format() == IndexFormat::Uint16);
+            let wasteful = gpu_mesh.shrink
+                && (verts * SHRINK_BELOW).max(MIN_VERTS) < gpu_mesh.max_verts
+                && (indxs * SHRINK_BELOW).max(MIN_INDXS) < gpu_mesh.max_indxs;
 
-            queue.write_buffer(&gpu_mesh.vertbuf, 0, bytemuck::cast_slice(&self.verts));
+            let resized = if !fits {
+                // Room to grow past whichever is bigger, so a mesh that outgrew its buffers (or had empty ones) doesn't just fit exactly
+                Some((
+                    (gpu_mesh.max_verts.max(verts) * GROWTH).max(MIN_VERTS),
+                    (gpu_mesh.max_indxs.max(indxs) * GROWTH).max(MIN_INDXS),
+                ))
+            } else if wasteful {
+                // Still leave some room to grow
+                Some(((verts * GROWTH).max(MIN_VERTS), (indxs * GROWTH).max(MIN_INDXS)))
+            } else {
+                None
+            };
+            if let Some((max_verts, max_indxs)) = resized {
+                let mut resized = self.upload_sized(device, max_verts, max_indxs);
+                resized.shrink = gpu_mesh.shrink;
+                std::mem::replace(gpu_mesh, resized).destroy();
+                return;
+            }
 
-            queue.write_buffer(&gpu_mesh.indxbuf, 0, bytemuck::cast_slice(&self.indxs));
+            queue.write_buffer(&gpu_mesh.vertbuf, 0, bytemuck::cast_slice(&self.verts));
+            queue.write_buffer(&gpu_mesh.indxbuf, 0, &index_bytes(&self.indxs, gpu_mesh.index_format, 0));
             gpu_mesh.num_indxs = self.indxs.len() as u32;
         }
     }
@@ -84,9 +160,25 @@ pub mod types {
         pub indxbuf: Buffer,
         pub num_indxs: u32,
         pub max_indxs: u32,
+        /// What size the indices in indxbuf are
+        pub index_format: IndexFormat,
+        /// Whether update_gpu_mesh() gives it smaller buffers when it's using a lot less than it has
+        pub shrink: bool,
 
         _phantom_vert_data: core::marker::PhantomData<V>,
     }
+    impl<V> GPUMesh<V> {
+        /// How much GPU memory the buffers take up
+        pub fn bytes_allocated(&self) -> u64 {
+            self.max_verts as u64 * std::mem::size_of::<V>() as u64 + self.max_indxs as u64 * index_size(self.index_format) as u64
+        }
+
+        /// Frees the buffers now, instead of whenever wgpu gets around to it
+        pub fn destroy(&self) {
+            self.vertbuf.destroy();
+            self.indxbuf.destroy();
+        }
+    }
 
     pub trait BindGroupSource<DATA> {
         fn bind_group_layout(&self, device: &Device) -> BindGroupLayout;
@@ -101,38 +193,128 @@ pub mod types {
         fn update_bind_group(&self, data: &DATA, queue: &Queue) {}
     }
 
+    /// Why a WgpuCtx couldn't be made
+    #[derive(Debug)]
+    pub enum CtxError {
+        /// None of these backends had an adapter, not even a fallback (software) one
+        NoAdapter { backends: Backends },
+        /// The adapter can't draw to the window
+        IncompatibleSurface { adapter: AdapterInfo },
+        /// The adapter was found, but wouldn't give us a device
+        Device { adapter: AdapterInfo, error: RequestDeviceError },
+    }
+    impl std::fmt::Display for CtxError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                CtxError::NoAdapter { backends } => {
+                    let names = [
+                        (Backends::VULKAN, "Vulkan"),
+                        (Backends::METAL, "Metal"),
+                        (Backends::DX12, "DirectX 12"),
+                        (Backends::DX11, "DirectX 11"),
+                        (Backends::GL, "OpenGL"),
+                        (Backends::BROWSER_WEBGPU, "WebGPU"),
+                    ].iter().filter(|(flag, _)| backends.contains(*flag)).map(|(_, name)| *name).collect::<Vec<_>>();
+                    write!(f, "No graphics adapter found, not even a software one (tried {}). \
+                        Set WGPU_BACKEND to try a specific one.", names.join(", "))
+                },
+                CtxError::IncompatibleSurface { adapter } =>
+                    write!(f, "{} ({:?}) can't draw to the window", adapter.name, adapter.backend),
+                CtxError::Device { adapter, error } =>
+                    write!(f, "Couldn't get a device from {} ({:?}): {}", adapter.name, adapter.backend, error),
+            }
+        }
+    }
+    impl std::error::Error for CtxError {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            match self {
+                CtxError::Device { error, .. } => Some(error),
+                _ => None
+            }
+        }
+    }
+
+    /// Why util::read_texture() couldn't read a texture back
+    #[derive(Debug)]
+    pub enum ReadbackError {
+        /// It only does 8-bit RGBA and BGRA
+        UnsupportedFormat(TextureFormat),
+        /// The buffer the texture was copied into couldn't be mapped (like if the device was lost)
+        Map(BufferAsyncError),
+    }
+    impl std::fmt::Display for ReadbackError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                ReadbackError::UnsupportedFormat(format) =>
+                    write!(f, "Can't read back {:?} textures, only 8-bit RGBA or BGRA", format),
+                ReadbackError::Map(error) => write!(f, "Mapping the readback buffer failed ({})", error),
+            }
+        }
+    }
+    impl std::error::Error for ReadbackError {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            match self {
+                ReadbackError::Map(error) => Some(error),
+                _ => None
+            }
+        }
+    }
+
+    /// Which backends to look for adapters on: all of them, unless WGPU_BACKEND (like "vulkan" or "gl") says otherwise
+    fn backends() -> Backends {
+        wgpu::util::backend_bits_from_env().unwrap_or_else(Backends::all)
+    }
+
+    /// The format headless() renders in
+    pub const OFFSCREEN_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+
     pub struct WgpuCtx {
         /// Used to create resources (buffers, pipelines, etc.) on the GPU
         pub device: Device,
         /// Used to update some resources (textures, buffers) and to render to the screen
         pub queue: Queue,
-        /// Used to render to the screen
-        pub surface: Surface,
+        /// Used to render to the screen (None for headless contexts, which only render to OffscreenTargets)
+        pub surface: Option<Surface>,
         /// Used to recreate the Surface on window resize
         pub config: SurfaceConfiguration,
         /// Used to call resize() when the size of the window hasn't changed
         /// (when weird stuff happens)
         pub size: winit::dpi::PhysicalSize<u32>,
+        /// What's doing the rendering
+        #[cfg_attr(not(test), allow(dead_code))]
+        pub adapter: AdapterInfo,
     }
     impl WgpuCtx {
         pub async fn new(
             window: &winit::window::Window,
             power_pref: PowerPreference,
-            device_desc: DeviceDescriptor<'_>,
+            mut device_desc: DeviceDescriptor<'_>,
             present_mode: PresentMode,
-        ) -> Self {
+        ) -> Result<Self, CtxError> {
             let size = window.inner_size();
     
-            let instance = wgpu::Instance::new(wgpu::Backends::all());
+            let backends = backends();
+            let instance = wgpu::Instance::new(backends);
             let surface = unsafe { instance.create_surface(window) };
-            let adapter = instance
-                .request_adapter(&wgpu::RequestAdapterOptions {
-                    power_preference: power_pref,
-                    compatible_surface: Some(&surface),
-                    force_fallback_adapter: false,
-                })
-                .await
-                .unwrap();
+            let mut adapter = None;
+            // A real GPU if there is one, otherwise a software rasterizer (like in a VM)
+            for force_fallback_adapter in [false, true] {
+                adapter = instance
+                    .request_adapter(&wgpu::RequestAdapterOptions {
+                        power_preference: power_pref,
+                        compatible_surface: Some(&surface),
+                        force_fallback_adapter,
+                    })
+                    .await;
+                if adapter.is_some() { break }
+            }
+            let adapter = adapter.ok_or(CtxError::NoAdapter { backends })?;
+            let info = adapter.get_info();
+            if info.device_type == DeviceType::Cpu {
+                eprintln!("No GPU found, rendering in software with {} ({:?})", info.name, info.backend);
+                // Software renderers don't always do everything a GPU does, but keep its texture size so big windows still fit
+                device_desc.limits = Limits::downlevel_defaults().using_resolution(adapter.limits());
+            }
     
             let (device, queue) = adapter
                 .request_device(
@@ -140,23 +322,66 @@ pub mod types {
                     None, // Trace path
                 )
                 .await
-                .unwrap();
+                .map_err(|error| CtxError::Device { adapter: info.clone(), error })?;
     
             let config = wgpu::SurfaceConfiguration {
                 usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-                format: surface.get_preferred_format(&adapter).unwrap(),
+                format: surface.get_preferred_format(&adapter).ok_or(CtxError::IncompatibleSurface { adapter: info.clone() })?,
                 width: size.width,
                 height: size.height,
-                present_mode: present_mode,
+                present_mode,
             };
             surface.configure(&device, &config);
     
-            Self {
-                device, queue, surface, config, size
-            }
+            Ok(Self {
+                device, queue, surface: Some(surface), config, size, adapter: info
+            })
+        }
+
+        /// A context without a window, for rendering into an OffscreenTarget.
+        /// Uses the fallback (software) adapter, so it works without a display or a GPU.
+        /// Unlike new() it ignores WGPU_BACKEND and only looks on `backends`, so what it renders with can be pinned down.
+        #[cfg_attr(not(test), allow(dead_code))]
+        pub async fn headless(backends: Backends, width: u32, height: u32) -> Result<Self, CtxError> {
+            let instance = wgpu::Instance::new(backends);
+            let adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: PowerPreference::default(),
+                    compatible_surface: None,
+                    force_fallback_adapter: true,
+                })
+                .await
+                .ok_or(CtxError::NoAdapter { backends })?;
+            let info = adapter.get_info();
+
+            let (device, queue) = adapter
+                .request_device(
+                    &DeviceDescriptor {
+                        label: None,
+                        features: Features::default(),
+                        // Software renderers don't always do everything a GPU does
+                        limits: Limits::downlevel_defaults()
+                    },
+                    None, // Trace path
+                )
+                .await
+                .map_err(|error| CtxError::Device { adapter: info.clone(), error })?;
+
+            // Nothing gets presented, but the size and format are still what everything else renders with
+            let config = wgpu::SurfaceConfiguration {
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                format: OFFSCREEN_FORMAT,
+                width,
+                height,
+                present_mode: PresentMode::Fifo,
+            };
+
+            Ok(Self {
+                device, queue, surface: None, config, size: winit::dpi::PhysicalSize::new(width, height), adapter: info
+            })
         }
 
-        pub async fn default(window: &winit::window::Window) -> Self {
+        pub async fn default(window: &winit::window::Window) -> Result<Self, CtxError> {
             Self::new(
                 window,
                 PowerPreference::default(),
@@ -174,10 +399,78 @@ pub mod types {
                 self.size = new_size;
                 self.config.width = new_size.width;
                 self.config.height = new_size.height;
-                self.surface.configure(&self.device, &self.config);
+                if let Some(surface) = &self.surface {
+                    surface.configure(&self.device, &self.config);
+                }
             }
         }
     }
+
+    /// A texture the size and format of the context's config, to render into instead of the window.
+    /// What's rendered can be read back to the CPU.
+    pub struct OffscreenTarget {
+        pub texture: Texture,
+        pub view: TextureView,
+        pub width: u32,
+        pub height: u32,
+        pub format: TextureFormat,
+    }
+    impl OffscreenTarget {
+        pub fn new(ctx: &WgpuCtx) -> Self {
+            let texture = ctx.device.create_texture(&TextureDescriptor {
+                label: Some("offscreen target"),
+                size: Extent3d {
+                    width: ctx.config.width,
+                    height: ctx.config.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: ctx.config.format,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            });
+            let view = texture.create_view(&TextureViewDescriptor::default());
+
+            Self { texture, view, width: ctx.config.width, height: ctx.config.height, format: ctx.config.format }
+        }
+
+        /// The rendered pixels, RGBA8 row by row from the top
+        pub fn read_pixels(&self, ctx: &WgpuCtx) -> Result<Vec<u8>, ReadbackError> {
+            util::read_texture(&ctx.device, &ctx.queue, &self.texture, self.width, self.height, self.format)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn index_format_fits_the_vertices() {
+            assert_eq!(index_format_for(0), IndexFormat::Uint16);
+            assert_eq!(index_format_for(65536), IndexFormat::Uint16);
+            assert_eq!(index_format_for(65537), IndexFormat::Uint32);
+
+            let mesh = CPUMesh { verts: vec![[0f32; 3]; 65537], indxs: vec![65536] };
+            assert_eq!(mesh.index_format(), IndexFormat::Uint32);
+        }
+
+        #[test]
+        fn index_bytes_are_padded() {
+            let indxs = [1, 2, 3];
+            let mut expected = [1u16, 2, 3].iter().flat_map(|i| i.to_ne_bytes()).collect::<Vec<u8>>();
+            // Up to a multiple of 4 bytes, even without any room asked for
+            expected.extend([0, 0]);
+            assert_eq!(index_bytes(&indxs, IndexFormat::Uint16, 0), expected);
+            assert_eq!(index_bytes(&indxs, IndexFormat::Uint16, 4), expected);
+            expected.extend([0; 4]);
+            assert_eq!(index_bytes(&indxs, IndexFormat::Uint16, 5), expected);
+
+            let expected = [1u32, 2, 3, 0].iter().flat_map(|i| i.to_ne_bytes()).collect::<Vec<u8>>();
+            assert_eq!(index_bytes(&indxs, IndexFormat::Uint32, 0), expected[..12]);
+            assert_eq!(index_bytes(&indxs, IndexFormat::Uint32, 4), expected);
+        }
+    }
 }
 
 pub mod util {
@@ -190,11 +483,74 @@ pub mod util {
         data: &[T],
         usage: BufferUsages,
     ) -> Buffer {
-        return device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some(&format!("{:?} Buffer", usage)),
             contents: bytemuck::cast_slice(data),
             usage,
+        })
+    }
+
+    /// Copies an 8-bit RGBA or BGRA texture back to the CPU, waiting for the GPU to finish with it first.
+    /// The pixels come back as RGBA, tightly packed (without the padding wgpu needs on the end of each row).
+    pub fn read_texture(
+        device: &Device, queue: &Queue, texture: &Texture, width: u32, height: u32, format: TextureFormat
+    ) -> Result<Vec<u8>, ReadbackError> {
+        // Windows usually want BGRA, so screenshots of them come out with red and blue swapped
+        let bgra = match format {
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => true,
+            _ => return Err(ReadbackError::UnsupportedFormat(format))
+        };
+
+        // Copies have to be done in rows of a multiple of 256 bytes
+        let row_bytes = width * 4;
+        let padded_row_bytes = row_bytes.div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT) * COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("readback buffer"),
+            size: (padded_row_bytes * height) as BufferAddress,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
         });
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_row_bytes),
+                    rows_per_image: std::num::NonZeroU32::new(height),
+                },
+            },
+            Extent3d { width, height, depth_or_array_layers: 1 },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let mapped = slice.map_async(MapMode::Read);
+        device.poll(Maintain::Wait);
+        pollster::block_on(mapped).map_err(ReadbackError::Map)?;
+
+        let padded = slice.get_mapped_range();
+        let mut pixels = Vec::with_capacity((row_bytes * height) as usize);
+        for row in padded.chunks(padded_row_bytes as usize) {
+            pixels.extend_from_slice(&row[..row_bytes as usize]);
+        }
+        drop(padded);
+        buffer.unmap();
+
+        if bgra {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        Ok(pixels)
     }
 
     pub fn draw_mesh<'a, 'b: 'a, V>(
@@ -203,7 +559,7 @@ pub mod util {
         instances: u32,
     ) {
         pass.set_vertex_buffer(0, mesh.vertbuf.slice(..));
-        pass.set_index_buffer(mesh.indxbuf.slice(..), INDEX_FORMAT);
+        pass.set_index_buffer(mesh.indxbuf.slice(..), mesh.index_format);
 
         pass.draw_indexed(0..mesh.num_indxs, 0, 0..instances);
     }
@@ -0,0 +1,97 @@
+use cgmath::InnerSpace;
+use wgpu::*;
+use super::lib::types::BindGroupSource;
+use super::lib::util::fast_buffer;
+
+/// The sun, plus a bit of light everywhere so faces facing away from it aren't pitch black
+pub struct LightData {
+    /// Radians around the sky, 0 is towards +x, increasing towards +z
+    pub azimuth: f32,
+    /// Radians above the horizon
+    pub elevation: f32,
+    /// How bright faces facing away from the sun are (0..1)
+    pub ambient: f32,
+}
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightUniform {
+    /// Points towards the sun
+    sun_dir: [f32; 3],
+    ambient: f32,
+}
+
+impl LightData {
+    /// Which way the sun is, from the ground
+    pub fn sun_dir(&self) -> cgmath::Vector3<f32> {
+        cgmath::vec3(
+            self.elevation.cos() * self.azimuth.cos(),
+            self.elevation.sin(),
+            self.elevation.cos() * self.azimuth.sin(),
+        ).normalize()
+    }
+    pub fn uniform(&self) -> LightUniform {
+        LightUniform {
+            sun_dir: self.sun_dir().into(),
+            ambient: self.ambient,
+        }
+    }
+}
+
+impl Default for LightData {
+    // Mid-morning, coming in at an angle so every side of a block looks a bit different
+    fn default() -> Self {
+        Self {
+            azimuth: 0.6,
+            elevation: 0.9,
+            ambient: 0.35,
+        }
+    }
+}
+
+impl BindGroupSource<Buffer> for LightData {
+    fn bind_group_layout(&self, device: &Device) -> BindGroupLayout {
+        device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+            // Light buffer
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            ],
+        })
+    }
+
+    fn bind_group(
+        &self,
+        device: &Device,
+        _queue: &Queue,
+        layout: &BindGroupLayout,
+    ) -> (BindGroup, Buffer) {
+        let buffer = fast_buffer(
+            device,
+            &[self.uniform()],
+            BufferUsages::COPY_DST | BufferUsages::UNIFORM,
+        );
+        let group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        (group, buffer)
+    }
+
+    fn update_bind_group(&self, data: &Buffer, queue: &Queue) {
+        queue.write_buffer(data, 0, bytemuck::cast_slice(&[self.uniform()]));
+    }
+}
@@ -1,22 +1,39 @@
+use std::time::Instant;
+
 use wgpu::*;
 use block_mesh::ndshape::{ConstShape};
 use block_mesh::{greedy_quads, GreedyQuadsBuffer, MergeVoxel, RIGHT_HANDED_Y_UP_CONFIG};
 
+mod shade;
 pub mod camera;
 use camera::CameraData;
+pub mod controller;
+pub mod light;
+use light::LightData;
 
 mod lib;
 pub use lib::types::*;
 pub use lib::util;
 pub mod texture;
-use texture::Texture;
+use texture::{BlockAtlas, Texture};
 
 use crate::terrain;
+use crate::workers::WorkerPool;
 
 #[repr(C)]
 #[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy, Debug)]
 pub struct Vertex {
     pub pos: [f32; 3],
+    /// In blocks, so a merged quad repeats the texture instead of stretching it
+    pub uv: [f32; 2],
+    /// Which texture in the block atlas
+    pub layer: u32,
+    /// Which way the face points, for lighting
+    pub normal: [f32; 3],
+    /// Ambient occlusion, 0 (in a corner) to 1 (out in the open)
+    pub ao: f32,
+    /// How bright the sky light and block light on the face are (0 to 1)
+    pub light: [f32; 2],
 }
 impl Vertex {
     fn desc<'a>() -> VertexBufferLayout<'a> {
@@ -27,28 +44,88 @@ impl Vertex {
                 offset: 0,
                 shader_location: 0,
                 format: VertexFormat::Float32x3,
+            }, VertexAttribute {
+                offset: std::mem::size_of::<[f32; 3]>() as BufferAddress,
+                shader_location: 1,
+                format: VertexFormat::Float32x2,
+            }, VertexAttribute {
+                offset: std::mem::size_of::<[f32; 5]>() as BufferAddress,
+                shader_location: 2,
+                format: VertexFormat::Uint32,
+            }, VertexAttribute {
+                offset: std::mem::size_of::<[f32; 6]>() as BufferAddress,
+                shader_location: 3,
+                format: VertexFormat::Float32x3,
+            }, VertexAttribute {
+                offset: std::mem::size_of::<[f32; 9]>() as BufferAddress,
+                shader_location: 4,
+                format: VertexFormat::Float32,
+            }, VertexAttribute {
+                offset: std::mem::size_of::<[f32; 10]>() as BufferAddress,
+                shader_location: 5,
+                format: VertexFormat::Float32x2,
             }],
         }
     }
 }
 
+/// Voxels that know what their faces look like
+pub trait TexturedVoxel: MergeVoxel {
+    /// The block atlas layer for the face pointing along `normal`
+    fn texture_layer(&self, normal: [f32; 3]) -> u32;
+}
+impl TexturedVoxel for terrain::Block {
+    fn texture_layer(&self, normal: [f32; 3]) -> u32 {
+        let textures = &self.ty().textures;
+        if normal[1] > 0.5 {
+            textures.top
+        } else if normal[1] < -0.5 {
+            textures.bottom
+        } else {
+            textures.side
+        }
+    }
+}
+
+
+/// What happened in the last render(), for diagnostics
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RenderStats {
+    /// Chunks that were in view
+    pub drawn: usize,
+    /// Chunks that were skipped because they were off screen
+    pub culled: usize,
+    /// GPU memory taken up by all the chunk meshes (loaded or not)
+    pub mesh_bytes: u64,
+}
+
 pub struct ChunkRender {
     /// Shaders, general draw config, specs for the vertex buffers, etc.
     pipeline: RenderPipeline,
+    /// The block textures
+    atlas_group: BindGroup,
     /// The chunk mesh
     pub chunk_gpu_meshes: terrain::PosHash<GPUMesh<Vertex>>,
-    stage_buffer: GreedyQuadsBuffer
+    stage_buffer: GreedyQuadsBuffer,
+    /// The newest mesh job sent off for each chunk, so older ones that finish late get ignored
+    mesh_versions: terrain::PosHash<u64>,
+    next_version: u64
 }
 impl ChunkRender {
     pub fn new(
         ctx: &WgpuCtx,
         camera: &CameraData,
+        atlas: &BlockAtlas,
+        light: &LightData,
         voxels: usize
     ) -> Self {
+        let atlas_layout = atlas.bind_group_layout(&ctx.device);
+        let (atlas_group, _) = atlas.bind_group(&ctx.device, &ctx.queue, &atlas_layout);
+
         // Pipeline specs for uniforms
         let layout = ctx.device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: None,
-            bind_group_layouts: &[&camera.bind_group_layout(&ctx.device)],
+            bind_group_layouts: &[&camera.bind_group_layout(&ctx.device), &atlas_layout, &light.bind_group_layout(&ctx.device)],
             push_constant_ranges: &[],
         });
         
@@ -95,77 +172,154 @@ impl ChunkRender {
         
         Self {
             pipeline,
+            atlas_group,
             chunk_gpu_meshes: terrain::PosHash::new(),
-            stage_buffer: GreedyQuadsBuffer::new(voxels)
+            stage_buffer: GreedyQuadsBuffer::new(voxels),
+            mesh_versions: terrain::PosHash::new(),
+            next_version: 0
         }
     }
     
-    pub fn cache_chunk_mesh<B: MergeVoxel, SH: ConstShape<u32, 3>>(
+    #[allow(clippy::too_many_arguments)]
+    pub fn cache_chunk_mesh<B: TexturedVoxel, SH: ConstShape<u32, 3>>(
         &mut self, 
         ctx: &WgpuCtx,
         pos: terrain::ChunkPos,
         shape: &SH,
         data: &[B], 
+        light: &[u8],
         size: u32,
         voxel_size: f32
     ) {
-        let faces = RIGHT_HANDED_Y_UP_CONFIG.faces;
-
-        greedy_quads(
-            data,
-            shape,
-            [0,0,0], // start (include padding)
-            [size+1,size+1,size+1], // end (include padding)
-            &faces,
-            &mut self.stage_buffer // Don't allocate new memory - just pass the same mutable buffer each time.
+        let mesh = build_chunk_mesh(&mut self.stage_buffer, pos, shape, data, light, size, voxel_size);
+
+        self.chunk_gpu_meshes.insert(
+            pos,
+            upload_chunk_mesh(ctx, &mesh)
         );
+    }
 
-        let mut mesh = CPUMesh {
-            verts: vec![],
-            indxs: vec![]
-        };
-        // For each face of a cube
-        for i in 0..6 {
-            // OrientedBlockFace are used to make a quad(rectangle) face a certain way.
-            let face = faces[i];
-            let quads = &self.stage_buffer.quads.groups[i];
-
-            for quad in quads.iter() {
-                // Get data
-                let verts = face.quad_mesh_positions(quad, voxel_size);
-                let indxs = face.quad_mesh_indices(mesh.verts.len() as u32);
-
-                // Convert to the right format
-                for vert in verts {
-                    mesh.verts.push(Vertex { pos: [
-                        vert[0] + pos[0] as f32 * size as f32,
-                        vert[1] + pos[1] as f32 * size as f32,
-                        vert[2] + pos[2] as f32 * size as f32
-                    ] });
-                }
-                for indx in indxs {
-                    mesh.indxs.push(indx as Index);
+    /// Sends the chunks that changed since the last call off to be remeshed by the workers.
+    /// Stops at `deadline` (after sending at least one), leaving the rest for next time.
+    pub fn remesh_dirty(&mut self, world: &mut terrain::TerrainState, workers: &WorkerPool, deadline: Instant) {
+        let mut dirty = world.take_dirty().into_iter();
+        for pos in dirty.by_ref() {
+            // It might have been unloaded since it changed
+            let data = match world.chunks.get(&pos) {
+                Some(data) => Box::new(data.to_blocks()),
+                None => continue
+            };
+            let light = world.padded_light(pos);
+            self.next_version += 1;
+            self.mesh_versions.insert(pos, self.next_version);
+            workers.mesh(pos, self.next_version, data, light);
+            if Instant::now() >= deadline { break }
+        }
+        for pos in dirty {
+            world.mark_dirty(pos);
+        }
+    }
+
+    /// Uploads up to `budget` meshes that the workers have finished.
+    /// Chunks that already have a mesh reuse its buffers instead of allocating new ones.
+    pub fn upload_finished(&mut self, ctx: &WgpuCtx, workers: &WorkerPool, budget: usize) {
+        let mut uploaded = 0;
+        while uploaded < budget {
+            let (pos, version, mesh) = match workers.try_recv_mesh() {
+                Some(finished) => finished,
+                None => break
+            };
+            // Skip meshes of chunks that changed again (or were unloaded) after the mesh was started
+            if self.mesh_versions.get(&pos) != Some(&version) { continue }
+
+            match self.chunk_gpu_meshes.get_mut(&pos) {
+                Some(gpu_mesh) => mesh.update_gpu_mesh(gpu_mesh, &ctx.device, &ctx.queue),
+                None => {
+                    self.chunk_gpu_meshes.insert(pos, upload_chunk_mesh(ctx, &mesh));
                 }
             }
+            uploaded += 1;
         }
+    }
 
-        const MAX_FACES: u32 = 16 * 16 * 16 * 6 / 2;
-        const MAX_VERTS: u32 = MAX_FACES * 4; // four points
-        const MAX_INDXS: u32 = MAX_FACES * 6; // two triangles(3) from the points
+    /// Frees the GPU buffers of a chunk that isn't loaded anymore
+    pub fn unload(&mut self, pos: terrain::ChunkPos) {
+        self.mesh_versions.remove(&pos);
+        if let Some(mesh) = self.chunk_gpu_meshes.remove(&pos) {
+            mesh.destroy();
+        }
+    }
 
-        self.chunk_gpu_meshes.insert(
-            pos,
-            mesh.upload_sized(&ctx.device, MAX_VERTS, MAX_INDXS)
-        );
+    /// Meshes every loaded chunk right away, instead of on the workers (for rendering a world once, offscreen)
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn mesh_world(&mut self, ctx: &WgpuCtx, world: &mut terrain::TerrainState) {
+        world.take_dirty();
+        let positions = world.chunks.keys().copied().collect::<Vec<_>>();
+        for pos in positions {
+            let data = world.chunks[&pos].to_blocks();
+            let light = world.padded_light(pos);
+            self.cache_chunk_mesh(ctx, pos, &terrain::ChunkShape {}, &data[..], &light[..], terrain::SIZE, 1.);
+        }
     }
 
-    pub fn render<'c>(&self, ctx: &WgpuCtx, depth_texture: &Texture, camera_group: &BindGroup, chunks: &[terrain::ChunkPos]) -> Result<(), SurfaceError> {
+    /// Draws the chunks that are in view to the window, returning how many were drawn and how many were off screen
+    pub fn render(
+        &self,
+        ctx: &WgpuCtx,
+        depth_texture: &Texture,
+        camera: &CameraData,
+        camera_group: &BindGroup,
+        light_group: &BindGroup,
+        chunks: &[terrain::ChunkPos]
+    ) -> Result<RenderStats, SurfaceError> {
         // Get textures to render to
-        let output = ctx.surface.get_current_texture()?;
+        let output = ctx.surface.as_ref().expect("Headless contexts can only render_to() a texture").get_current_texture()?;
         let view = output
         .texture
         .create_view(&wgpu::TextureViewDescriptor::default());
-        
+
+        let stats = self.render_to(ctx, &view, depth_texture, camera, camera_group, light_group, chunks);
+        output.present();
+
+        Ok(stats)
+    }
+
+    /// Draws the same thing render() does into an offscreen texture and reads it back, returning the RGBA8 pixels row by row.
+    /// It's the size of the window, and `depth_texture` has to be too.
+    /// This doesn't copy the frame that was presented (window surfaces can't be copied from on every backend),
+    /// so it allocates a new texture and renders again every time, which is fine for the odd screenshot.
+    pub fn screenshot(
+        &self,
+        ctx: &WgpuCtx,
+        depth_texture: &Texture,
+        camera: &CameraData,
+        camera_group: &BindGroup,
+        light_group: &BindGroup,
+        chunks: &[terrain::ChunkPos]
+    ) -> Result<Vec<u8>, ReadbackError> {
+        let target = OffscreenTarget::new(ctx);
+        self.render_to(ctx, &target.view, depth_texture, camera, camera_group, light_group, chunks);
+        target.read_pixels(ctx)
+    }
+
+    /// Draws the chunks that are in view into any texture the size of the context's config (like an OffscreenTarget)
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_to(
+        &self,
+        ctx: &WgpuCtx,
+        view: &TextureView,
+        depth_texture: &Texture,
+        camera: &CameraData,
+        camera_group: &BindGroup,
+        light_group: &BindGroup,
+        chunks: &[terrain::ChunkPos]
+    ) -> RenderStats {
+        let frustum = camera.frustum();
+        let mut stats = RenderStats {
+            mesh_bytes: self.chunk_gpu_meshes.values().map(GPUMesh::bytes_allocated).sum(),
+            ..RenderStats::default()
+        };
+
         // Encodes render passes
         let mut encoder = ctx.device
         .create_command_encoder(&CommandEncoderDescriptor { label: None });
@@ -176,7 +330,7 @@ impl ChunkRender {
             let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
                 label: None,
                 color_attachments: &[RenderPassColorAttachment {
-                    view: &view,
+                    view,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
@@ -199,21 +353,107 @@ impl ChunkRender {
             });
             
             pass.set_bind_group(0, camera_group, &[]);
+            pass.set_bind_group(1, &self.atlas_group, &[]);
+            pass.set_bind_group(2, light_group, &[]);
             pass.set_pipeline(&self.pipeline);
             
             for pos in chunks {
-                match self.chunk_gpu_meshes.get(pos) {
-                    Some(mesh) => {
+                // Chunks that are still loading don't have a mesh yet
+                if let Some(mesh) = self.chunk_gpu_meshes.get(pos) {
+                    let size = terrain::SIZE as f32;
+                    let min = pos.map(|c| c as f32 * size);
+                    if frustum.intersects_aabb(min, min.map(|c| c + size)) {
                         util::draw_mesh(&mut pass, mesh, 1);
-                    },
-                    None => { panic!("Mesh not set - {:?}", pos) }
+                        stats.drawn += 1;
+                    } else {
+                        stats.culled += 1;
+                    }
                 }
             }
         };
 
         ctx.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
-        
-        Ok(())
+
+        stats
     }
-}
\ No newline at end of file
+}
+
+/// Uploads a chunk's first mesh. Its buffers start out just big enough and grow (or shrink back) as it's remeshed.
+fn upload_chunk_mesh(ctx: &WgpuCtx, mesh: &CPUMesh<Vertex>) -> GPUMesh<Vertex> {
+    let mut gpu_mesh = mesh.upload(&ctx.device);
+    gpu_mesh.shrink = true;
+    gpu_mesh
+}
+
+/// How bright a light level looks (each level down is a bit darker than the last)
+fn light_brightness(level: u8) -> f32 {
+    0.8f32.powi((terrain::MAX_LIGHT - level) as i32)
+}
+
+/// Turns a padded chunk into a mesh, in world coordinates. `light` is the chunk's light, laid out like `data`.
+pub fn build_chunk_mesh<B: TexturedVoxel, SH: ConstShape<u32, 3>>(
+    buffer: &mut GreedyQuadsBuffer,
+    pos: terrain::ChunkPos,
+    shape: &SH,
+    data: &[B], 
+    light: &[u8],
+    size: u32,
+    voxel_size: f32
+) -> CPUMesh<Vertex> {
+    let faces = RIGHT_HANDED_Y_UP_CONFIG.faces;
+    let voxels = shade::shade::<B, SH>(data, light, &faces);
+
+    greedy_quads(
+        &voxels,
+        shape,
+        [0,0,0], // start (include padding)
+        [size+1,size+1,size+1], // end (include padding)
+        &faces,
+        buffer // Don't allocate new memory - just pass the same mutable buffer each time.
+    );
+
+    let mut mesh = CPUMesh {
+        verts: vec![],
+        indxs: vec![]
+    };
+    // For each face of a cube
+    for (i, face) in faces.iter().enumerate() {
+        // OrientedBlockFace are used to make a quad(rectangle) face a certain way.
+        let quads = &buffer.quads.groups[i];
+
+        for quad in quads.iter() {
+            // Get data
+            let verts = face.quad_mesh_positions(quad, voxel_size);
+            // Only faces of the same block with the same shading get merged, so any voxel in the quad will do
+            let voxel = &voxels[SH::linearize(quad.minimum) as usize];
+            let ao = voxel.ao.corners(i);
+            let light = [terrain::Channel::Sky, terrain::Channel::Block].map(|channel| light_brightness(channel.get(voxel.light[i])));
+            let start = mesh.verts.len() as u32;
+            let indxs = shade::orient_diagonal(face.quad_mesh_indices(start), start, ao);
+            let normal = face.quad_mesh_normals()[0];
+            let layer = voxel.voxel.texture_layer(normal);
+            let uvs = face.tex_coords(RIGHT_HANDED_Y_UP_CONFIG.u_flip_face, true, quad);
+
+            // Convert to the right format (minus 1 for the padding, so local 1 is the chunk's corner)
+            for ((vert, uv), ao) in verts.into_iter().zip(uvs).zip(ao) {
+                mesh.verts.push(Vertex {
+                    pos: [
+                        vert[0] + (pos[0] as f32 * size as f32 - 1.) * voxel_size,
+                        vert[1] + (pos[1] as f32 * size as f32 - 1.) * voxel_size,
+                        vert[2] + (pos[2] as f32 * size as f32 - 1.) * voxel_size
+                    ],
+                    uv,
+                    layer,
+                    normal,
+                    ao: ao as f32 / 3.,
+                    light
+                });
+            }
+            for indx in indxs {
+                mesh.indxs.push(indx);
+            }
+        }
+    }
+
+    mesh
+}
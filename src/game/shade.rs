@@ -0,0 +1,104 @@
+// How light each face of a chunk is: ambient occlusion (the corners of a face get darker the more blocks are crowded around them)
+// and the light level of the block in front of it
+
+use block_mesh::ndshape::ConstShape;
+use block_mesh::{MergeVoxel, OrientedBlockFace, UnorientedQuad, Voxel};
+
+/// How lit each corner of each face of a voxel is, from 0 (tucked into a corner) to 3 (out in the open).
+/// 2 bits per corner, 8 per face, faces in the same order as the ones passed to shade(),
+/// corners in the same order as OrientedBlockFace::quad_corners().
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct FaceAo(u64);
+impl FaceAo {
+    pub fn corners(&self, face: usize) -> [u32; 4] {
+        [0, 1, 2, 3].map(|corner| ((self.0 >> (face * 8 + corner * 2)) & 0b11) as u32)
+    }
+
+    fn set(&mut self, face: usize, corner: usize, ao: u32) {
+        self.0 |= (ao as u64) << (face * 8 + corner * 2);
+    }
+}
+
+/// A voxel with its AO and light, so greedy meshing only merges faces that are shaded the same.
+/// (That's any face of the voxel, not just the one being merged, so a few more quads get made than needed.)
+pub struct ShadedVoxel<'a, B> {
+    pub voxel: &'a B,
+    pub ao: FaceAo,
+    /// The light (packed, like terrain::ChunkLight) in front of each face
+    pub light: [u8; 6],
+}
+impl<B: Voxel> Voxel for ShadedVoxel<'_, B> {
+    fn is_empty(&self) -> bool {
+        self.voxel.is_empty()
+    }
+
+    fn is_opaque(&self) -> bool {
+        self.voxel.is_opaque()
+    }
+}
+impl<B: MergeVoxel> MergeVoxel for ShadedVoxel<'_, B> {
+    type MergeValue = (B::MergeValue, FaceAo, [u8; 6]);
+
+    fn merge_value(&self) -> Self::MergeValue {
+        (self.voxel.merge_value(), self.ao, self.light)
+    }
+}
+
+/// Works out the AO and light of every face in a padded chunk, from its blocks and light (laid out the same way).
+/// The padding itself doesn't get any, since it doesn't get meshed.
+pub fn shade<'a, B: Voxel, SH: ConstShape<u32, 3>>(data: &'a [B], light: &[u8], faces: &[OrientedBlockFace; 6]) -> Vec<ShadedVoxel<'a, B>> {
+    // Each face's normal and the directions its quads' u and v go in
+    let unit = UnorientedQuad { minimum: [1, 1, 1], width: 1, height: 1 };
+    let axes = faces.map(|face| {
+        let corners = face.quad_corners(&unit).map(|c| -> [u32; 3] { c.into() });
+        let step = |to: [u32; 3]| [0, 1, 2].map(|axis| to[axis] as i32 - corners[0][axis] as i32);
+        (face.signed_normal().into(), step(corners[1]), step(corners[2]))
+    });
+    let index = |p: [i32; 3]| SH::linearize(p.map(|c| c as u32)) as usize;
+    let opaque = |p: [i32; 3]| data[index(p)].is_opaque();
+    let add = |a: [i32; 3], b: [i32; 3], scale: i32| [0, 1, 2].map(|axis| a[axis] + b[axis] * scale);
+
+    data.iter().enumerate().map(|(i, voxel)| {
+        let mut ao = FaceAo::default();
+        let mut face_light = [0; 6];
+        let p = SH::delinearize(i as u32);
+        let padding = (0..3).any(|axis| p[axis] == 0 || p[axis] == SH::ARRAY[axis] - 1);
+        if !padding && !voxel.is_empty() {
+            let p = p.map(|c| c as i32);
+            for (face, &(n, u, v)) in axes.iter().enumerate() {
+                // The air in front of the face; if there isn't any the face doesn't get drawn anyway
+                let front: [i32; 3] = add(p, n, 1);
+                if opaque(front) { continue }
+                face_light[face] = light[index(front)];
+
+                for corner in 0..4 {
+                    let du = if corner & 1 == 0 { -1 } else { 1 };
+                    let dv = if corner & 2 == 0 { -1 } else { 1 };
+                    let side1 = opaque(add(front, u, du));
+                    let side2 = opaque(add(front, v, dv));
+                    let diagonal = opaque(add(add(front, u, du), v, dv));
+                    // Two sides block the corner completely, even if the diagonal is open
+                    let level = if side1 && side2 {
+                        0
+                    } else {
+                        3 - (side1 as u32 + side2 as u32 + diagonal as u32)
+                    };
+                    ao.set(face, corner, level);
+                }
+            }
+        }
+        ShadedVoxel { voxel, ao, light: face_light }
+    }).collect()
+}
+
+/// Quads get split into triangles along the corner 1 to 2 diagonal. Light is interpolated along that diagonal,
+/// so if the other one is lighter, split along that instead (otherwise the shading changes with the quad's orientation).
+pub fn orient_diagonal(indxs: [u32; 6], start: u32, ao: [u32; 4]) -> [u32; 6] {
+    if ao[0] + ao[3] > ao[1] + ao[2] {
+        // Turning the corners a quarter around the quad moves the diagonal and keeps the winding
+        const TURN: [u32; 4] = [1, 3, 0, 2];
+        indxs.map(|i| start + TURN[(i - start) as usize])
+    } else {
+        indxs
+    }
+}
@@ -1,6 +1,11 @@
 // Copied from https://sotrh.github.io/learn-wgpu/
 
+use std::num::NonZeroU32;
+use super::lib::types::BindGroupSource;
+
 pub struct Texture {
+    /// Not read, but kept with the view and sampler so everything about the texture is in one place
+    #[allow(dead_code)]
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler
@@ -44,4 +49,129 @@ impl Texture {
 
         Self { texture, view, sampler }
     }
-}
\ No newline at end of file
+
+    /// Loads an image of square tiles stacked on top of each other, as one tall texture.
+    /// The shader picks out a tile by its layer (how far down it is) and repeats it, so UVs past 1 tile it.
+    /// (Not a texture array, because wgpu's OpenGL backend only ever uploads the first layer of those.)
+    pub fn from_tile_strip(device: &wgpu::Device, queue: &wgpu::Queue, bytes: &[u8], label: &str) -> Result<Self, image::ImageError> {
+        let image = image::load_from_memory(bytes)?.to_rgba8();
+        let (width, height) = image.dimensions();
+        if width == 0 || width > height || height % width != 0 {
+            return Err(image::ImageError::Parameter(image::error::ParameterError::from_kind(
+                image::error::ParameterErrorKind::Generic(format!("a {}x{} image isn't a column of square tiles", width, height))
+            )));
+        }
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &image,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(4 * width),
+                rows_per_image: NonZeroU32::new(height),
+            },
+            size,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(
+            &wgpu::SamplerDescriptor {
+                // The shader does the repeating, within each tile
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                // Crisp pixels
+                mag_filter: wgpu::FilterMode::Nearest,
+                min_filter: wgpu::FilterMode::Nearest,
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                ..Default::default()
+            }
+        );
+
+        Ok(Self { texture, view, sampler })
+    }
+}
+
+/// The block textures (assets/blocks.png), one square tile per layer
+pub struct BlockAtlas {
+    pub texture: Texture
+}
+impl BlockAtlas {
+    pub fn load(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        // Built into the executable, so it runs from anywhere
+        let bytes = include_bytes!("../../assets/blocks.png");
+        Self {
+            texture: Texture::from_tile_strip(device, queue, bytes, "block atlas")
+                .expect("assets/blocks.png isn't a valid image")
+        }
+    }
+}
+
+impl BindGroupSource<()> for BlockAtlas {
+    fn bind_group_layout(&self, device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            ],
+        })
+    }
+
+    fn bind_group(
+        &self,
+        device: &wgpu::Device,
+        _queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+    ) -> (wgpu::BindGroup, ()) {
+        let group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&self.texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.texture.sampler),
+                },
+            ],
+        });
+
+        (group, ())
+    }
+}
@@ -0,0 +1,192 @@
+//! Golden-image tests: renders a few small, fixed scenes offscreen on a software renderer
+//! and compares them with reference images in tests/golden, so changes to how things look get noticed.
+//!
+//! They run with `cargo test`, writing what was rendered and a diff to target/golden for scenes that don't match,
+//! and are skipped (with a message) when there's no renderer like the one the references were recorded with.
+//! `GOLDEN_BLESS=1 cargo test golden` records what's rendered now as the new references (after checking the changes are on purpose).
+
+use std::path::{Path, PathBuf};
+
+use block_mesh::ndshape::ConstShape;
+use wgpu::{Backends, DeviceType};
+
+use crate::game::{self, BindGroupSource};
+use crate::terrain::{self, Block, ChunkPos, ChunkShape, TerrainState};
+
+/// The references were recorded with Mesa's llvmpipe over OpenGL. Other backends (and GPUs) round differently enough
+/// to fail, so the tests only run on a software OpenGL adapter.
+const BACKENDS: Backends = Backends::GL;
+const WIDTH: u32 = 160;
+const HEIGHT: u32 = 120;
+/// Most any channel of a pixel can be off by before it counts as different (software renderers round a bit differently)
+const TOLERANCE: u8 = 8;
+
+/// A world and where to look at it from
+struct Scene {
+    name: &'static str,
+    world: fn() -> TerrainState,
+    eye: [f32; 3],
+    target: [f32; 3],
+}
+
+const SCENES: [Scene; 2] = [
+    // Lit from above, with every kind of block and a lamp in a little cave
+    Scene { name: "blocks", world: blocks_world, eye: [-6., 9., -6.], target: [6., 3., 6.] },
+    // Generated terrain going off into the distance, to catch meshing and culling problems
+    Scene { name: "hills", world: hills_world, eye: [0., 22., -4.], target: [16., 8., 24.] },
+];
+
+fn blocks_world() -> TerrainState {
+    let mut world = TerrainState::new(Box::new(terrain::generator::SineHills));
+    // Flat ground, so nothing about it depends on a generator
+    for pos in chunks_around([0, 0, 0], 1) {
+        world.set_chunk(pos, |p| match p[1] {
+            i32::MIN..=0 => Block::STONE,
+            1..=2 => Block::DIRT,
+            3 => Block::GRASS,
+            _ => Block::AIR,
+        });
+    }
+    world.set_block([4, 4, 4], Block::STONE);
+    world.set_block([5, 4, 4], Block::DIRT);
+    world.set_block([6, 4, 4], Block::GRASS);
+    world.set_block([7, 4, 4], Block::GLASS);
+    // A step, for ambient occlusion in the corner
+    for x in 2..10 {
+        world.set_block([x, 4, 8], Block::DIRT);
+        world.set_block([x, 5, 9], Block::DIRT);
+    }
+    // A roofed-over hole with a lamp in it, lit only by block light
+    for x in 9..12 {
+        for z in 2..5 {
+            world.set_block([x, 3, z], Block::AIR);
+            world.set_block([x, 2, z], Block::AIR);
+            world.set_block([x, 4, z], Block::STONE);
+        }
+    }
+    world.set_block([9, 2, 2], Block::LAMP);
+    world.set_block([11, 3, 3], Block::AIR);
+    world.set_block([11, 4, 3], Block::AIR);
+    world
+}
+
+fn hills_world() -> TerrainState {
+    let mut world = TerrainState::new(terrain::generator::by_name("heightmap", 1).unwrap());
+    for pos in chunks_around([0, 0, 1], 2) {
+        world.generate_chunk(pos);
+    }
+    world
+}
+
+fn chunks_around(center: ChunkPos, radius: i32) -> Vec<ChunkPos> {
+    let mut chunks = vec![];
+    for x in -radius..=radius {
+        for y in -1..=1 {
+            for z in -radius..=radius {
+                chunks.push([center[0] + x, center[1] + y, center[2] + z]);
+            }
+        }
+    }
+    chunks
+}
+
+/// Renders a scene the same way the game does, returning RGBA8 pixels
+fn render(ctx: &game::WgpuCtx, scene: &Scene) -> Vec<u8> {
+    let camera = game::camera::CameraData {
+        eye: scene.eye.into(),
+        target: scene.target.into(),
+        up: cgmath::vec3(0., 1., 0.),
+
+        aspect: WIDTH as f32 / HEIGHT as f32,
+        fovy: 70.,
+        znear: 0.1,
+        zfar: 400.
+    };
+    let (camera_group, _) = camera.bind_group(&ctx.device, &ctx.queue, &camera.bind_group_layout(&ctx.device));
+    let light = game::light::LightData::default();
+    let (light_group, _) = light.bind_group(&ctx.device, &ctx.queue, &light.bind_group_layout(&ctx.device));
+    let atlas = game::texture::BlockAtlas::load(&ctx.device, &ctx.queue);
+    let depth_texture = game::texture::Texture::create_depth_texture(&ctx.device, &ctx.config, "depth tex");
+    let target = game::OffscreenTarget::new(ctx);
+
+    let mut world = (scene.world)();
+    let mut chunk_r = game::ChunkRender::new(ctx, &camera, &atlas, &light, ChunkShape::SIZE as usize);
+    chunk_r.mesh_world(ctx, &mut world);
+    let chunks = world.chunks.keys().copied().collect::<Vec<_>>();
+    chunk_r.render_to(ctx, &target.view, &depth_texture, &camera, &camera_group, &light_group, &chunks);
+
+    target.read_pixels(ctx).expect("Headless contexts render RGBA")
+}
+
+/// How many pixels are too different, and an image showing where they are (red) on top of a faded copy of the reference
+fn compare(expected: &image::RgbaImage, actual: &image::RgbaImage) -> (usize, image::RgbaImage) {
+    let mut wrong = 0;
+    let diff = image::RgbaImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        let (e, a) = (expected.get_pixel(x, y), actual.get_pixel(x, y));
+        let off = e.0.iter().zip(a.0.iter()).any(|(e, a)| e.abs_diff(*a) > TOLERANCE);
+        if off {
+            wrong += 1;
+            image::Rgba([255, 0, 0, 255])
+        } else {
+            let gray = ((e[0] as u16 + e[1] as u16 + e[2] as u16) / 12) as u8;
+            image::Rgba([gray, gray, gray, 255])
+        }
+    });
+    (wrong, diff)
+}
+
+/// Checks (or with GOLDEN_BLESS set, records) every scene
+#[test]
+fn golden_images() {
+    let bless = std::env::var_os("GOLDEN_BLESS").is_some();
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let references = root.join("tests/golden");
+    let output = root.join("target/golden");
+    std::fs::create_dir_all(&references).unwrap();
+    std::fs::create_dir_all(&output).unwrap();
+
+    let ctx = match pollster::block_on(game::WgpuCtx::headless(BACKENDS, WIDTH, HEIGHT)) {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            println!("Skipping the golden-image tests, couldn't render headless: {}", e);
+            return;
+        }
+    };
+    if ctx.adapter.device_type != DeviceType::Cpu {
+        println!("Skipping the golden-image tests, {} isn't a software renderer like the references were recorded with", ctx.adapter.name);
+        return;
+    }
+
+    let mut failures = vec![];
+    for scene in SCENES.iter() {
+        let pixels = render(&ctx, scene);
+        let actual = image::RgbaImage::from_raw(WIDTH, HEIGHT, pixels).unwrap();
+        let reference = references.join(format!("{}.png", scene.name));
+
+        if bless {
+            actual.save(&reference).unwrap();
+            println!("{}: recorded {}", scene.name, reference.display());
+            continue;
+        }
+        let expected = match image::open(&reference) {
+            Ok(expected) => expected.to_rgba8(),
+            Err(e) => {
+                failures.push(format!("{}: couldn't open {} ({}), run with GOLDEN_BLESS=1 to record it", scene.name, reference.display(), e));
+                continue;
+            }
+        };
+        if expected.dimensions() != actual.dimensions() {
+            failures.push(format!("{}: the reference is {:?} but the render is {:?}", scene.name, expected.dimensions(), actual.dimensions()));
+            continue;
+        }
+
+        let (wrong, diff) = compare(&expected, &actual);
+        if wrong > 0 {
+            let out = |kind: &str| -> PathBuf { output.join(format!("{}.{}.png", scene.name, kind)) };
+            actual.save(out("actual")).unwrap();
+            diff.save(out("diff")).unwrap();
+            failures.push(format!("{}: {} pixels are different, see {} and {}", scene.name, wrong, out("actual").display(), out("diff").display()));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
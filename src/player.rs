@@ -0,0 +1,91 @@
+use cgmath::{Point3, Vector3};
+
+use crate::terrain::{Aabb, TerrainState};
+
+const WIDTH: f32 = 0.6;
+const HEIGHT: f32 = 1.8;
+/// From the feet
+const EYE_HEIGHT: f32 = 1.62;
+/// Blocks per second per second
+const GRAVITY: f32 = 30.;
+/// Upwards speed when jumping, in blocks per second (about 1.25 blocks high)
+const JUMP_SPEED: f32 = 8.7;
+/// Fastest falling speed, so long falls can't tunnel through the ground
+const TERMINAL_SPEED: f32 = 50.;
+
+pub struct Player {
+    /// Bottom center of the bounding box
+    pub pos: Point3<f32>,
+    pub velocity: Vector3<f32>,
+    pub on_ground: bool,
+    /// No gravity, and up/down movement is controlled directly
+    pub flying: bool,
+}
+impl Player {
+    pub fn new(pos: Point3<f32>) -> Self {
+        Self {
+            pos,
+            velocity: cgmath::vec3(0., 0., 0.),
+            on_ground: false,
+            flying: false,
+        }
+    }
+
+    /// Puts the player so that their eye is at `eye`
+    pub fn at_eye(eye: Point3<f32>) -> Self {
+        Self::new(eye - cgmath::vec3(0., EYE_HEIGHT, 0.))
+    }
+
+    pub fn eye(&self) -> Point3<f32> {
+        self.pos + cgmath::vec3(0., EYE_HEIGHT, 0.)
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        Aabb {
+            min: [self.pos.x - WIDTH / 2., self.pos.y, self.pos.z - WIDTH / 2.],
+            max: [self.pos.x + WIDTH / 2., self.pos.y + HEIGHT, self.pos.z + WIDTH / 2.],
+        }
+    }
+
+    /// Whether a block there would be inside the player
+    pub fn overlaps_block(&self, block: [i32; 3]) -> bool {
+        let aabb = self.aabb();
+        (0..3).all(|axis| aabb.min[axis] < block[axis] as f32 + 1. && aabb.max[axis] > block[axis] as f32)
+    }
+
+    /// Moves the player, sliding along whatever's in the way.
+    /// `wish` is the velocity the controls are asking for (only its vertical part is used when flying),
+    /// `dt` is the time since the last update, in seconds.
+    pub fn update(&mut self, world: &TerrainState, wish: Vector3<f32>, jump: bool, dt: f32) {
+        // Wait for the ground to load instead of falling through it
+        let feet = [self.pos.x.floor() as i32, self.pos.y.floor() as i32, self.pos.z.floor() as i32];
+        if world.get_block(feet).is_none() { return }
+
+        self.velocity.x = wish.x;
+        self.velocity.z = wish.z;
+        if self.flying {
+            self.velocity.y = wish.y;
+        } else {
+            if jump && self.on_ground {
+                self.velocity.y = JUMP_SPEED;
+            }
+            self.velocity.y = (self.velocity.y - GRAVITY * dt).max(-TERMINAL_SPEED);
+        }
+
+        // One axis at a time, so hitting a wall only stops movement into the wall.
+        // Vertical first, so standing on the ground doesn't catch on the edges of blocks.
+        self.on_ground = false;
+        for axis in [1, 0, 2] {
+            let want = self.velocity[axis] * dt;
+            let moved = world.sweep_aabb(&self.aabb(), axis, want);
+            self.pos[axis] += moved;
+
+            if moved != want {
+                if axis == 1 && want < 0. {
+                    self.on_ground = true;
+                }
+                self.velocity[axis] = 0.;
+            }
+        }
+    }
+}
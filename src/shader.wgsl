@@ -1,10 +1,19 @@
 struct VertexInput {
     [[location(0)]] position: vec3<f32>;
+    [[location(1)]] uv: vec2<f32>;
+    [[location(2)]] layer: u32;
+    [[location(3)]] normal: vec3<f32>;
+    [[location(4)]] ao: f32;
+    [[location(5)]] light: vec2<f32>;
 };
 
 struct VertexOutput {
     [[builtin(position)]] clip_position: vec4<f32>;
-    [[location(0)]] model_position: vec3<f32>;
+    [[location(0)]] uv: vec2<f32>;
+    [[location(1), interpolate(flat)]] layer: u32;
+    [[location(2)]] normal: vec3<f32>;
+    [[location(3)]] ao: f32;
+    [[location(4)]] light: vec2<f32>;
 };
 
 struct Camera {
@@ -19,18 +28,47 @@ fn vs_main(
 ) -> VertexOutput {
     var out: VertexOutput;
     out.clip_position = camera.transform * vec4<f32>(model.position, 1.0);
-    out.model_position = model.position;
+    out.uv = model.uv;
+    out.layer = model.layer;
+    out.normal = model.normal;
+    out.ao = model.ao;
+    out.light = model.light;
     return out;
 }
 
 // Fragment shader
 
+// One square tile per block texture, stacked on top of each other (tile `layer` is `layer` tiles down)
+[[group(1), binding(0)]]
+var t_blocks: texture_2d<f32>;
+[[group(1), binding(1)]]
+var s_blocks: sampler;
+
+struct Light {
+    // Points towards the sun
+    sun_dir: vec3<f32>;
+    ambient: f32;
+};
+[[group(2), binding(0)]]
+var<uniform> light: Light;
+
 [[stage(fragment)]]
 fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-    return vec4<f32>(
-        sin(in.model_position.x),
-        sin(in.model_position.y),
-        sin(in.model_position.z),
-        1.
-    );
+    // Repeat the tile across the whole face
+    let size = textureDimensions(t_blocks);
+    let tiles = f32(size.y / size.x);
+    let uv = vec2<f32>(fract(in.uv.x), (f32(in.layer) + fract(in.uv.y)) / tiles);
+    let color = textureSample(t_blocks, s_blocks, uv);
+    // See-through parts of textures (like glass) are either fully there or not at all
+    if (color.a < 0.5) {
+        discard;
+    }
+    // Lambert: faces get less light the more they turn away from the sun
+    let diffuse = max(dot(normalize(in.normal), light.sun_dir), 0.0);
+    // Corners crowded by other blocks get less of every kind of light
+    let occlusion = 0.4 + 0.6 * in.ao;
+    // Sunlight only gets as far as the sky light does, so caves stay dark; light from blocks doesn't care about the sun
+    let sun = (light.ambient + (1.0 - light.ambient) * diffuse) * in.light.x;
+    let brightness = max(sun, in.light.y) * occlusion;
+    return vec4<f32>(color.rgb * brightness, color.a);
 }
\ No newline at end of file
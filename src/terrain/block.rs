@@ -0,0 +1,80 @@
+use block_mesh::{MergeVoxel, Voxel};
+
+/// Index into BLOCK_TYPES
+pub type BlockId = u16;
+
+/// Everything the game needs to know about a kind of block
+#[derive(Debug)]
+pub struct BlockType {
+    pub name: &'static str,
+    /// Hides the faces of the blocks next to it, and blocks light
+    pub opaque: bool,
+    /// Stops things from moving through it
+    pub collides: bool,
+    /// Gets faces in the chunk mesh (air doesn't)
+    pub visible: bool,
+    /// Which layers of assets/blocks.png its faces use
+    pub textures: FaceTextures,
+    /// Block light it gives off (0 to MAX_LIGHT)
+    pub emits: u8,
+}
+
+/// Layers of the block texture atlas, for each side of a block
+#[derive(Debug, Clone, Copy)]
+pub struct FaceTextures {
+    pub top: u32,
+    pub side: u32,
+    pub bottom: u32,
+}
+impl FaceTextures {
+    pub const fn all(layer: u32) -> Self {
+        Self { top: layer, side: layer, bottom: layer }
+    }
+}
+
+// The registry: a block's ID is its position in this list, so only ever add to the end
+// (saved worlds store the IDs)
+pub static BLOCK_TYPES: [BlockType; 6] = [
+    BlockType { name: "air",   opaque: false, collides: false, visible: false, textures: FaceTextures::all(0), emits: 0 },
+    BlockType { name: "stone", opaque: true,  collides: true,  visible: true,  textures: FaceTextures::all(0), emits: 0 },
+    BlockType { name: "dirt",  opaque: true,  collides: true,  visible: true,  textures: FaceTextures::all(1), emits: 0 },
+    BlockType { name: "grass", opaque: true,  collides: true,  visible: true,  textures: FaceTextures { top: 2, side: 3, bottom: 1 }, emits: 0 },
+    BlockType { name: "glass", opaque: false, collides: true,  visible: true,  textures: FaceTextures::all(4), emits: 0 },
+    BlockType { name: "lamp",  opaque: true,  collides: true,  visible: true,  textures: FaceTextures::all(5), emits: 14 },
+];
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Block {
+    pub id: BlockId
+}
+impl Block {
+    pub const AIR: Block = Block { id: 0 };
+    pub const STONE: Block = Block { id: 1 };
+    pub const DIRT: Block = Block { id: 2 };
+    pub const GRASS: Block = Block { id: 3 };
+    pub const GLASS: Block = Block { id: 4 };
+    pub const LAMP: Block = Block { id: 5 };
+
+    pub fn ty(&self) -> &'static BlockType {
+        &BLOCK_TYPES[self.id as usize]
+    }
+}
+
+impl Voxel for Block {
+    fn is_empty(&self) -> bool {
+        ! self.ty().visible
+    }
+
+    fn is_opaque(&self) -> bool {
+        self.ty().opaque
+    }
+}
+
+impl MergeVoxel for Block {
+    // Only faces of the same type of block get merged together
+    type MergeValue = BlockId;
+
+    fn merge_value(&self) -> Self::MergeValue {
+        self.id
+    }
+}
@@ -0,0 +1,46 @@
+use super::TerrainState;
+
+/// Touching isn't overlapping: boxes are shrunk by this much when looking for blocks they overlap
+const EPSILON: f32 = 1e-4;
+
+/// An axis-aligned box, in world coordinates (block [x, y, z] is the box from (x, y, z) to (x+1, y+1, z+1))
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl TerrainState {
+    /// How far a box can move along one axis (0, 1 or 2) before it hits a block that collides,
+    /// out of the `delta` it wants to move. Blocks it's already stuck inside don't stop it, so it can get out.
+    /// Chunks that aren't loaded count as empty.
+    pub fn sweep_aabb(&self, aabb: &Aabb, axis: usize, delta: f32) -> f32 {
+        if delta == 0. { return 0. }
+
+        // The blocks the box passes through on its way
+        let mut min = aabb.min;
+        let mut max = aabb.max;
+        if delta > 0. { max[axis] += delta } else { min[axis] += delta }
+        let lo = min.map(|c| (c + EPSILON).floor() as i32);
+        let hi = max.map(|c| (c - EPSILON).floor() as i32);
+
+        let mut allowed = delta;
+        for x in lo[0]..=hi[0] {
+            for y in lo[1]..=hi[1] {
+                for z in lo[2]..=hi[2] {
+                    let collides = self.get_block([x, y, z]).is_some_and(|block| block.ty().collides);
+                    if !collides { continue }
+
+                    let block = [x, y, z][axis] as f32;
+                    if delta > 0. && block >= aabb.max[axis] - EPSILON {
+                        allowed = allowed.min(block - aabb.max[axis]);
+                    } else if delta < 0. && block + 1. <= aabb.min[axis] + EPSILON {
+                        allowed = allowed.max(block + 1. - aabb.min[axis]);
+                    }
+                }
+            }
+        }
+        // Never backwards, even if it was already a tiny bit inside something
+        if delta > 0. { allowed.max(0.) } else { allowed.min(0.) }
+    }
+}
@@ -0,0 +1,102 @@
+use super::Block;
+use super::noise::Fbm;
+
+/// Decides what block goes where in a new world.
+/// Must always give the same block for the same position, because chunks' padding is generated separately
+/// from the chunk it belongs to.
+pub trait TerrainGenerator: Send + Sync {
+    fn block_at(&self, world: [i32; 3]) -> Block;
+}
+
+/// Picks a generator by name (for config / command line), or None if there's no generator with that name
+pub fn by_name(name: &str, seed: u64) -> Option<Box<dyn TerrainGenerator>> {
+    match name {
+        "sine" => Some(Box::new(SineHills)),
+        "heightmap" => Some(Box::new(NoiseHeightmap::new(seed))),
+        "density" => Some(Box::new(NoiseDensity::new(seed))),
+        _ => None
+    }
+}
+
+/// Grass on top, a couple of dirt, then stone, for terrain with a surface at `height`
+fn layers(y: i32, height: f64) -> Block {
+    let y = y as f64;
+    if y < height - 3. {
+        Block::STONE
+    } else if y < height - 1. {
+        Block::DIRT
+    } else if y < height {
+        Block::GRASS
+    } else {
+        Block::AIR
+    }
+}
+
+/// The original test terrain: diagonal rolling hills. Doesn't use the seed.
+pub struct SineHills;
+impl TerrainGenerator for SineHills {
+    fn block_at(&self, [x, y, z]: [i32; 3]) -> Block {
+        layers(y, (x as f64 / 16. + z as f64 / 8.).sin() * 8. + 8.)
+    }
+}
+
+/// Hills from 2D fractal noise
+pub struct NoiseHeightmap {
+    pub noise: Fbm,
+    /// Where the surface is on average
+    pub base_height: f64,
+    /// How far above and below base_height it goes
+    pub amplitude: f64,
+}
+impl NoiseHeightmap {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            noise: Fbm::new(seed, 5, 96.),
+            base_height: 8.,
+            amplitude: 16.,
+        }
+    }
+}
+impl TerrainGenerator for NoiseHeightmap {
+    fn block_at(&self, [x, y, z]: [i32; 3]) -> Block {
+        layers(y, self.base_height + self.noise.get2(x as f64, z as f64) * self.amplitude)
+    }
+}
+
+/// Terrain from 3D fractal noise, so there are overhangs and floating bits, not just hills.
+/// A block is solid where the noise plus a bias that falls off with height is positive.
+pub struct NoiseDensity {
+    pub noise: Fbm,
+    /// Where the density is 0 without any noise
+    pub base_height: f64,
+    /// How many blocks of height it takes to cancel out the strongest noise
+    pub squash: f64,
+}
+impl NoiseDensity {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            noise: Fbm::new(seed, 4, 48.),
+            base_height: 8.,
+            squash: 24.,
+        }
+    }
+
+    fn density(&self, x: i32, y: i32, z: i32) -> f64 {
+        self.noise.get3(x as f64, y as f64, z as f64) + (self.base_height - y as f64) / self.squash
+    }
+}
+impl TerrainGenerator for NoiseDensity {
+    fn block_at(&self, [x, y, z]: [i32; 3]) -> Block {
+        if self.density(x, y, z) <= 0. {
+            return Block::AIR;
+        }
+        // Cover the tops with grass and dirt, like layers() does
+        if self.density(x, y + 1, z) <= 0. {
+            Block::GRASS
+        } else if self.density(x, y + 3, z) <= 0. {
+            Block::DIRT
+        } else {
+            Block::STONE
+        }
+    }
+}
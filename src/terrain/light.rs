@@ -0,0 +1,446 @@
+// Sky light and block light, spread out by flood fill.
+// Each block's light is the brightest it gets from any source, minus one for every step away from it.
+// Sky light is the exception: at full brightness it goes straight down forever, so everything under open sky is fully lit.
+
+use std::collections::VecDeque;
+
+use block_mesh::ndshape::ConstShape;
+
+use super::{
+    local_to_world, neighbor_local, padding_copies, padding_range, world_to_local, ChunkPos, ChunkShape, TerrainState, NEIGHBOR_OFFSETS, SIZE
+};
+
+/// The brightest light gets (sunlight, and the most a block can give off)
+pub const MAX_LIGHT: u8 = 15;
+
+const VOLUME: usize = (SIZE * SIZE * SIZE) as usize;
+
+/// The light of each of a chunk's own blocks (not the padding), sky light in the top 4 bits and block light in the bottom 4.
+/// Like ChunkStorage, chunks lit the same all over (solid rock, open sky) only store one level.
+#[derive(Clone, Debug)]
+pub enum ChunkLight {
+    /// Every block has the same light
+    Uniform(u8),
+    Mixed(Box<[u8; VOLUME]>),
+}
+impl ChunkLight {
+    pub fn get(&self, i: usize) -> u8 {
+        match self {
+            Self::Uniform(packed) => *packed,
+            Self::Mixed(light) => light[i],
+        }
+    }
+
+    fn set(&mut self, i: usize, packed: u8) {
+        if let Self::Uniform(fill) = *self {
+            if fill == packed { return }
+            *self = Self::Mixed(Box::new([fill; VOLUME]));
+        }
+        if let Self::Mixed(light) = self {
+            light[i] = packed;
+        }
+    }
+
+    /// Copies the light of a row of `out.len()` blocks along x, starting at `i`
+    fn copy_row(&self, i: usize, out: &mut [u8]) {
+        match self {
+            Self::Uniform(packed) => out.fill(*packed),
+            Self::Mixed(light) => out.copy_from_slice(&light[i..i + out.len()]),
+        }
+    }
+
+    /// Goes back to Uniform if every block ended up with the same light
+    fn compact(&mut self) {
+        if let Self::Mixed(light) = self {
+            if light.iter().all(|packed| *packed == light[0]) {
+                *self = Self::Uniform(light[0]);
+            }
+        }
+    }
+
+    /// Roughly how much memory this chunk's light takes up
+    pub fn size_bytes(&self) -> usize {
+        std::mem::size_of::<Self>() + match self {
+            Self::Uniform(_) => 0,
+            Self::Mixed(_) => VOLUME,
+        }
+    }
+}
+/// Light for a whole padded chunk, laid out like ChunkData, for meshing
+pub type PaddedLight = [u8; ChunkShape::SIZE as usize];
+
+/// The two kinds of light, which spread separately
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Channel {
+    /// From above
+    Sky,
+    /// From blocks that give off light
+    Block,
+}
+impl Channel {
+    pub fn get(self, packed: u8) -> u8 {
+        match self {
+            Channel::Sky => packed >> 4,
+            Channel::Block => packed & 0x0f,
+        }
+    }
+
+    fn with(self, packed: u8, level: u8) -> u8 {
+        match self {
+            Channel::Sky => packed & 0x0f | level << 4,
+            Channel::Block => packed & 0xf0 | level,
+        }
+    }
+}
+
+const FACES: [[i32; 3]; 6] = [[1, 0, 0], [-1, 0, 0], [0, 1, 0], [0, -1, 0], [0, 0, 1], [0, 0, -1]];
+const DOWN: [i32; 3] = [0, -1, 0];
+
+fn step(pos: [i32; 3], dir: [i32; 3]) -> [i32; 3] {
+    [pos[0] + dir[0], pos[1] + dir[1], pos[2] + dir[2]]
+}
+
+fn light_index(local: [u32; 3]) -> usize {
+    (local[0] - 1 + SIZE * (local[1] - 1 + SIZE * (local[2] - 1))) as usize
+}
+
+impl TerrainState {
+    /// Both kinds of light at a world position, packed (see ChunkLight), or None if the chunk it's in isn't loaded
+    pub fn get_light(&self, world: [i32; 3]) -> Option<u8> {
+        let (chunk, local) = world_to_local(world);
+        self.light.get(&chunk).map(|light| light.get(light_index(local)))
+    }
+
+    /// One kind of light at a world position, 0 if it isn't loaded
+    pub fn light_level(&self, world: [i32; 3], channel: Channel) -> u8 {
+        self.get_light(world).map_or(0, |packed| channel.get(packed))
+    }
+
+    /// The light of a chunk and its padding. Padding in chunks that aren't loaded counts as under open sky.
+    pub fn padded_light(&self, pos: ChunkPos) -> Box<PaddedLight> {
+        let mut light = Box::new([MAX_LIGHT << 4; ChunkShape::SIZE as usize]);
+        // The chunk itself and each neighbor's part of the padding, a row along x at a time
+        for offset in NEIGHBOR_OFFSETS.into_iter().chain([[0, 0, 0]]) {
+            let source = match self.light.get(&step(pos, offset)) {
+                Some(source) => source,
+                None => continue
+            };
+            let xs = padding_range(offset[0]);
+            for z in padding_range(offset[2]) {
+                for y in padding_range(offset[1]) {
+                    let start = ChunkShape::linearize([*xs.start(), y, z]) as usize;
+                    let from = neighbor_local([*xs.start(), y, z], offset).map(|c| c as u32);
+                    source.copy_row(light_index(from), &mut light[start..=start + (xs.end() - xs.start()) as usize]);
+                }
+            }
+        }
+        light
+    }
+
+    /// Changes the light at a world position, marking every mesh it shows up in as dirty
+    fn set_light_level(&mut self, world: [i32; 3], channel: Channel, level: u8) {
+        let (chunk, local) = world_to_local(world);
+        let light = match self.light.get_mut(&chunk) {
+            Some(light) => light,
+            None => return
+        };
+        let i = light_index(local);
+        let packed = light.get(i);
+        if channel.get(packed) == level { return }
+        light.set(i, channel.with(packed, level));
+
+        self.dirty.insert(chunk);
+        for (neighbor, _) in padding_copies(chunk, local) {
+            if self.chunks.contains_key(&neighbor) {
+                self.dirty.insert(neighbor);
+            }
+        }
+    }
+
+    /// Light can spread into it (it's loaded and not opaque)
+    fn lets_light_through(&self, world: [i32; 3]) -> bool {
+        self.get_block(world).is_some_and(|block| !block.ty().opaque)
+    }
+
+    /// On top of the highest loaded chunk, which is assumed to be under open sky
+    fn open_sky(&self, world: [i32; 3]) -> bool {
+        let (chunk, local) = world_to_local(world);
+        local[1] == SIZE && !self.light.contains_key(&[chunk[0], chunk[1] + 1, chunk[2]])
+    }
+
+    /// Spreads light outwards from every position in `queue`, as far as it goes
+    fn spread_light(&mut self, channel: Channel, mut queue: VecDeque<[i32; 3]>) {
+        while let Some(pos) = queue.pop_front() {
+            let level = self.light_level(pos, channel);
+            if level <= 1 { continue }
+
+            for dir in FACES {
+                let next = step(pos, dir);
+                if !self.lets_light_through(next) { continue }
+
+                let spread = if channel == Channel::Sky && dir == DOWN && level == MAX_LIGHT {
+                    MAX_LIGHT
+                } else {
+                    level - 1
+                };
+                if self.light_level(next, channel) < spread {
+                    self.set_light_level(next, channel, spread);
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+
+    /// Takes away light that came from the positions in `queue` (which have already been darkened, and had the levels with them),
+    /// then lets whatever other light is around fill the gap back in
+    fn unspread_light(&mut self, channel: Channel, mut queue: VecDeque<([i32; 3], u8)>) {
+        let mut respread = VecDeque::new();
+        while let Some((pos, old)) = queue.pop_front() {
+            for dir in FACES {
+                let next = step(pos, dir);
+                let level = self.light_level(next, channel);
+                if level == 0 { continue }
+
+                // Dimmer than where it came from means it could have come from there
+                let from_here = level < old
+                    || (channel == Channel::Sky && dir == DOWN && old == MAX_LIGHT && level == MAX_LIGHT);
+                if from_here {
+                    self.set_light_level(next, channel, 0);
+                    queue.push_back((next, level));
+
+                    // Light sources don't go out just because the light around them did
+                    let emits = self.get_block(next).map_or(0, |block| block.ty().emits);
+                    if channel == Channel::Block && emits > 0 {
+                        self.set_light_level(next, channel, emits);
+                        respread.push_back(next);
+                    }
+                } else {
+                    // Lit by something else, which can now spread into the dark
+                    respread.push_back(next);
+                }
+            }
+        }
+        self.spread_light(channel, respread);
+    }
+
+    /// Works out the light of a chunk that was just loaded, letting it spread into (and out of) the chunks around it
+    pub(super) fn light_chunk(&mut self, pos: ChunkPos) {
+        self.light.insert(pos, ChunkLight::Uniform(0));
+        let up = [pos[0], pos[1] + 1, pos[2]];
+        let down = [pos[0], pos[1] - 1, pos[2]];
+
+        for channel in [Channel::Sky, Channel::Block] {
+            let mut queue = VecDeque::new();
+
+            // Light coming in from loaded neighbors, through the blocks touching this chunk
+            for dir in FACES {
+                if !self.light.contains_key(&step(pos, dir)) { continue }
+                let axis = (0..3).find(|&axis| dir[axis] != 0).unwrap();
+                for i in 1..=SIZE {
+                    for j in 1..=SIZE {
+                        let mut local = [0; 3];
+                        local[axis] = if dir[axis] > 0 { SIZE } else { 1 };
+                        local[(axis + 1) % 3] = i;
+                        local[(axis + 2) % 3] = j;
+                        queue.push_back(step(local_to_world(pos, local), dir));
+                    }
+                }
+            }
+
+            match channel {
+                // Nothing loaded above means open sky, so sunlight goes down each column until something stops it
+                Channel::Sky if !self.light.contains_key(&up) => {
+                    for x in 1..=SIZE {
+                        for z in 1..=SIZE {
+                            for y in (1..=SIZE).rev() {
+                                let world = local_to_world(pos, [x, y, z]);
+                                if !self.lets_light_through(world) { break }
+                                self.set_light_level(world, channel, MAX_LIGHT);
+                                queue.push_back(world);
+                            }
+                        }
+                    }
+                },
+                Channel::Sky => {},
+                Channel::Block => {
+                    for i in 0..ChunkShape::SIZE {
+                        let local = ChunkShape::delinearize(i);
+                        if super::is_padding(local) { continue }
+                        let emits = self.chunks[&pos].get(i as usize).ty().emits;
+                        if emits > 0 {
+                            let world = local_to_world(pos, local);
+                            self.set_light_level(world, channel, emits);
+                            queue.push_back(world);
+                        }
+                    }
+                }
+            }
+
+            self.spread_light(channel, queue);
+        }
+        // Most chunks are all dark or all lit once they're done, so they don't need a level for every block
+        if let Some(light) = self.light.get_mut(&pos) {
+            light.compact();
+        }
+
+        // The chunk below thought it was under open sky, and might not be anymore
+        if self.light.contains_key(&down) {
+            let mut queue = VecDeque::new();
+            for x in 1..=SIZE {
+                for z in 1..=SIZE {
+                    let top = local_to_world(down, [x, SIZE, z]);
+                    let sky = Channel::Sky;
+                    if self.light_level(top, sky) == MAX_LIGHT && self.light_level(step(top, [0, 1, 0]), sky) != MAX_LIGHT {
+                        self.set_light_level(top, sky, 0);
+                        queue.push_back((top, MAX_LIGHT));
+                    }
+                }
+            }
+            self.unspread_light(Channel::Sky, queue);
+        }
+    }
+
+    /// Fixes up the light around a block that just changed
+    pub(super) fn relight_block(&mut self, world: [i32; 3]) {
+        let (chunk, _) = world_to_local(world);
+        if !self.light.contains_key(&chunk) { return }
+
+        for channel in [Channel::Sky, Channel::Block] {
+            // Take away whatever light it had and passed on (which also lets the light around it back in, if it's see-through)
+            let old = self.light_level(world, channel);
+            self.set_light_level(world, channel, 0);
+            self.unspread_light(channel, VecDeque::from([(world, old)]));
+
+            let mut queue = VecDeque::new();
+            if channel == Channel::Sky && self.open_sky(world) && self.lets_light_through(world) {
+                self.set_light_level(world, channel, MAX_LIGHT);
+                queue.push_back(world);
+            }
+            let emits = self.get_block(world).map_or(0, |block| block.ty().emits);
+            if channel == Channel::Block && emits > 0 {
+                self.set_light_level(world, channel, emits);
+                queue.push_back(world);
+            }
+            self.spread_light(channel, queue);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::generator::SineHills;
+    use crate::terrain::Block;
+
+    /// Blocks -16..16 on every axis
+    const CHUNKS: [ChunkPos; 8] = [[-1, -1, -1], [0, -1, -1], [-1, 0, -1], [0, 0, -1], [-1, -1, 0], [0, -1, 0], [-1, 0, 0], [0, 0, 0]];
+
+    /// Loads `chunks` in that order, all lit from scratch
+    fn world_from(chunks: &[ChunkPos], block_at: impl Fn([i32; 3]) -> Block) -> TerrainState {
+        let mut world = TerrainState::new(Box::new(SineHills));
+        for &pos in chunks {
+            world.set_chunk(pos, &block_at);
+        }
+        world
+    }
+
+    /// Stone ground, with a stone roof over part of it so there's some shade
+    fn roofed(pos: [i32; 3]) -> Block {
+        let under_roof = (0..8).contains(&pos[0]) && (0..8).contains(&pos[2]);
+        if pos[1] <= -12 || (pos[1] == 4 && under_roof) { Block::STONE } else { Block::AIR }
+    }
+
+    fn assert_same_light(actual: &TerrainState, expected: &TerrainState, chunks: &[ChunkPos]) {
+        for &pos in chunks {
+            for x in 1..=SIZE {
+                for y in 1..=SIZE {
+                    for z in 1..=SIZE {
+                        let world = local_to_world(pos, [x, y, z]);
+                        let (a, e) = (actual.get_light(world).unwrap(), expected.get_light(world).unwrap());
+                        assert_eq!(
+                            (Channel::Sky.get(a), Channel::Block.get(a)), (Channel::Sky.get(e), Channel::Block.get(e)),
+                            "(sky, block) light at {:?}", world
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn relight_matches_lighting_from_scratch() {
+        let edits = [
+            // A hole in the roof, and a lamp and some glass under it
+            ([4, 4, 4], Block::AIR),
+            ([2, 0, 2], Block::LAMP),
+            ([3, 0, 2], Block::GLASS),
+            // A pit in the ground, going down into the chunks below
+            ([-8, -12, -8], Block::AIR),
+            ([-8, -13, -8], Block::AIR),
+            // A wall on the chunk border, and a lamp right next to it in the other chunk
+            ([-1, -11, 5], Block::STONE),
+            ([-1, -10, 5], Block::STONE),
+            ([0, -11, 5], Block::LAMP),
+            // Filling the hole back in
+            ([4, 4, 4], Block::STONE),
+        ];
+
+        let mut world = world_from(&CHUNKS, roofed);
+        for (i, &(pos, block)) in edits.iter().enumerate() {
+            assert!(world.set_block(pos, block));
+
+            let done = &edits[..=i];
+            let expected = world_from(&CHUNKS, |p| {
+                done.iter().rev().find(|(pos, _)| *pos == p).map_or_else(|| roofed(p), |(_, block)| *block)
+            });
+            assert_same_light(&world, &expected, &CHUNKS);
+        }
+    }
+
+    #[test]
+    fn removing_light_crosses_chunk_borders() {
+        // Solid rock, except for a tunnel along x that goes from chunk -1 into chunk 0
+        let tunnel = |p: [i32; 3]| if p[1] == -8 && p[2] == -8 { Block::AIR } else { Block::STONE };
+        let mut world = world_from(&CHUNKS, tunnel);
+        assert!(world.set_block([-3, -8, -8], Block::LAMP));
+        assert_eq!(world.light_level([2, -8, -8], Channel::Block), 14 - 5);
+
+        assert!(world.set_block([-3, -8, -8], Block::AIR));
+        for x in -16..16 {
+            assert_eq!(world.light_level([x, -8, -8], Channel::Block), 0, "block light at x = {}", x);
+        }
+        assert_same_light(&world, &world_from(&CHUNKS, tunnel), &CHUNKS);
+    }
+
+    #[test]
+    fn lamp_placed_then_removed() {
+        let mut world = world_from(&CHUNKS, roofed);
+        let before = world_from(&CHUNKS, roofed);
+
+        assert!(world.set_block([4, 0, 4], Block::LAMP));
+        assert_eq!(world.light_level([4, 1, 4], Channel::Block), 13);
+        assert_eq!(world.light_level([4, 0, 10], Channel::Block), 8);
+
+        assert!(world.set_block([4, 0, 4], Block::AIR));
+        assert_same_light(&world, &before, &CHUNKS);
+    }
+
+    #[test]
+    fn load_order_does_not_matter() {
+        let column = [[0, -1, 0], [0, 0, 0]];
+        let below_first = world_from(&column, roofed);
+        let above_first = world_from(&[column[1], column[0]], roofed);
+        assert_same_light(&below_first, &above_first, &column);
+
+        // The roof shades the chunk below, and the open sky reaches down through it
+        assert!(above_first.light_level([4, -5, 4], Channel::Sky) < MAX_LIGHT);
+        assert_eq!(above_first.light_level([12, -5, 12], Channel::Sky), MAX_LIGHT);
+    }
+
+    #[test]
+    fn evenly_lit_chunks_store_one_level() {
+        let world = world_from(&[[0, -1, 0], [0, 0, 0]], |p| if p[1] < 0 { Block::STONE } else { Block::AIR });
+        assert!(matches!(world.light[&[0, -1, 0]], ChunkLight::Uniform(0)));
+        assert!(matches!(world.light[&[0, 0, 0]], ChunkLight::Uniform(packed) if packed == MAX_LIGHT << 4));
+    }
+}
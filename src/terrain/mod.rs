@@ -0,0 +1,334 @@
+use block_mesh::ndshape::{ConstShape, ConstShape3u32};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::Arc;
+
+mod block;
+pub use block::*;
+mod storage;
+pub use storage::ChunkStorage;
+pub mod region;
+mod noise;
+pub mod generator;
+pub use generator::TerrainGenerator;
+pub mod stream;
+mod raycast;
+mod collision;
+pub use collision::Aabb;
+mod light;
+pub use light::{Channel, ChunkLight, PaddedLight, MAX_LIGHT};
+
+// 16x16x16 with 1-block padding on edges
+pub const SIZE: u32 = 16;
+pub type ChunkShape = ConstShape3u32<{SIZE + 2},{SIZE + 2},{SIZE + 2}>;
+pub type ChunkPos = [i32; 3];
+pub type PosHash<T> = std::collections::HashMap<ChunkPos, T>;
+pub type ChunkData = [Block; ChunkShape::SIZE as usize];
+
+/// Local coordinates include the padding, so the chunk's own blocks are at 1..=SIZE
+pub fn local_to_world(chunk: ChunkPos, local: [u32; 3]) -> [i32; 3] {
+    [
+        chunk[0] * SIZE as i32 + local[0] as i32 - 1,
+        chunk[1] * SIZE as i32 + local[1] as i32 - 1,
+        chunk[2] * SIZE as i32 + local[2] as i32 - 1
+    ]
+}
+
+/// The chunk a block is in, and its (non-padding) local position in that chunk
+pub fn world_to_local(world: [i32; 3]) -> (ChunkPos, [u32; 3]) {
+    let s = SIZE as i32;
+    (
+        [world[0].div_euclid(s), world[1].div_euclid(s), world[2].div_euclid(s)],
+        [
+            world[0].rem_euclid(s) as u32 + 1,
+            world[1].rem_euclid(s) as u32 + 1,
+            world[2].rem_euclid(s) as u32 + 1
+        ]
+    )
+}
+
+pub fn is_padding(local: [u32; 3]) -> bool {
+    local.iter().any(|&c| c == 0 || c == SIZE + 1)
+}
+
+/// Where a local position is in the neighbor at `offset`, which might be outside it
+fn neighbor_local(local: [u32; 3], offset: [i32; 3]) -> [i32; 3] {
+    [0, 1, 2].map(|axis| local[axis] as i32 - offset[axis] * SIZE as i32)
+}
+
+/// The local positions along one axis that come from the neighbor at `offset` on that axis:
+/// the padding on either side, or the chunk's own blocks for offset 0
+fn padding_range(offset: i32) -> RangeInclusive<u32> {
+    match offset {
+        -1 => 0..=0,
+        0 => 1..=SIZE,
+        _ => SIZE + 1..=SIZE + 1
+    }
+}
+
+/// The other chunks that have a copy of a block in their padding, and where it is in them
+fn padding_copies(chunk: ChunkPos, local: [u32; 3]) -> impl Iterator<Item = (ChunkPos, [u32; 3])> {
+    NEIGHBOR_OFFSETS.into_iter().filter_map(move |offset| {
+        // Only blocks on the edge are in other chunks' padding
+        let neighbor_local = neighbor_local(local, offset);
+        if neighbor_local.iter().any(|&c| c < 0 || c > SIZE as i32 + 1) { return None }
+
+        let neighbor = [chunk[0] + offset[0], chunk[1] + offset[1], chunk[2] + offset[2]];
+        Some((neighbor, neighbor_local.map(|c| c as u32)))
+    })
+}
+
+
+pub struct TerrainState {
+    pub chunks: PosHash<ChunkStorage>,
+    /// Chunks whose data (padding included) changed since the last take_dirty(), so their meshes are stale
+    dirty: HashSet<ChunkPos>,
+    /// Chunks changed with set_block(), which can't just be generated again if they're unloaded
+    edited: HashSet<ChunkPos>,
+    /// Sky and block light of every loaded chunk (worked out when it's loaded, not saved)
+    light: PosHash<ChunkLight>,
+    /// Makes new chunks (and the padding next to chunks that aren't loaded)
+    pub generator: Arc<dyn TerrainGenerator>
+}
+impl TerrainState {
+    pub fn new(generator: Box<dyn TerrainGenerator>) -> Self {
+        Self {
+            chunks: HashMap::new(),
+            dirty: HashSet::new(),
+            edited: HashSet::new(),
+            light: HashMap::new(),
+            generator: Arc::from(generator)
+        }
+    }
+
+    /// Gets the chunks that need remeshing, and forgets about them
+    pub fn take_dirty(&mut self) -> HashSet<ChunkPos> {
+        std::mem::take(&mut self.dirty)
+    }
+
+    /// Remeshes a chunk after the next take_dirty(), like it had changed again
+    pub fn mark_dirty(&mut self, pos: ChunkPos) {
+        self.dirty.insert(pos);
+    }
+
+    /// The block at a world position, or None if the chunk it's in isn't loaded
+    pub fn get_block(&self, world: [i32; 3]) -> Option<Block> {
+        let (chunk, local) = world_to_local(world);
+        self.chunks.get(&chunk).map(|data| data.get(ChunkShape::linearize(local) as usize))
+    }
+
+    /// Changes the block at a world position, keeping the neighboring chunks' padding and the light around it up to date.
+    /// Returns false (and changes nothing) if the chunk it's in isn't loaded.
+    /// Unlike the original plan for this API, it doesn't allocate a chunk that's missing: one made up here would cover up
+    /// the saved or generated chunk when that streams in, so edits outside the loaded area just don't happen.
+    pub fn set_block(&mut self, world: [i32; 3], block: Block) -> bool {
+        let (chunk, local) = world_to_local(world);
+        let data = match self.chunks.get_mut(&chunk) {
+            Some(data) => data,
+            None => return false
+        };
+        let index = ChunkShape::linearize(local) as usize;
+        if data.get(index) == block { return true }
+        data.set(index, block);
+        self.dirty.insert(chunk);
+        self.edited.insert(chunk);
+
+        for (neighbor, neighbor_local) in padding_copies(chunk, local) {
+            if let Some(data) = self.chunks.get_mut(&neighbor) {
+                data.set(ChunkShape::linearize(neighbor_local) as usize, block);
+                self.dirty.insert(neighbor);
+            }
+        }
+
+        self.relight_block(world);
+        true
+    }
+
+    /// Forgets a chunk, saving it to `dir` first if it was edited.
+    /// The neighbors' padding copies stay as they are, since the blocks didn't change.
+    pub fn unload_chunk(&mut self, dir: &Path, pos: ChunkPos) -> io::Result<()> {
+        if self.edited.contains(&pos) {
+            if let Some(chunk) = self.chunks.get(&pos) {
+                region::save_chunk(dir, pos, chunk)?;
+            }
+            self.edited.remove(&pos);
+        }
+        self.chunks.remove(&pos);
+        self.light.remove(&pos);
+        self.dirty.remove(&pos);
+        Ok(())
+    }
+
+    /// Makes a chunk with the world's generator (streaming does this on the workers instead)
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn generate_chunk(&mut self, pos: ChunkPos) {
+        let generator = self.generator.clone();
+        self.set_chunk(pos, |world| generator.block_at(world));
+    }
+
+    /// Fills a chunk from a function of world position. The padding is copied from the neighboring chunks if they're loaded,
+    /// otherwise it comes from the function too, so faces on the chunk border that a neighbor hides aren't meshed.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn set_chunk<F: Fn([i32; 3]) -> Block>(&mut self, pos: ChunkPos, func: F) {
+        let mut data = [Block::AIR; ChunkShape::SIZE as usize];
+        for (i, block) in data.iter_mut().enumerate() {
+            let local = ChunkShape::delinearize(i as u32);
+            let world = local_to_world(pos, local);
+            let generate = || func(world);
+
+            *block = if is_padding(local) {
+                self.get_block(world).unwrap_or_else(generate)
+            } else {
+                generate()
+            };
+        }
+        self.insert_chunk(pos, ChunkStorage::from_blocks(&data));
+    }
+
+    /// Adds a finished chunk, replacing its padding with loaded neighbors' blocks where it can, and lights it
+    pub fn insert_chunk(&mut self, pos: ChunkPos, chunk: ChunkStorage) {
+        self.chunks.insert(pos, chunk);
+        self.dirty.insert(pos);
+
+        // The neighbors' padding might have been generated before this chunk was, so bring it up to date too
+        for offset in NEIGHBOR_OFFSETS {
+            let neighbor = [pos[0] + offset[0], pos[1] + offset[1], pos[2] + offset[2]];
+            self.copy_padding(pos, offset);
+            self.copy_padding(neighbor, offset.map(|c| -c));
+        }
+
+        self.light_chunk(pos);
+    }
+
+    /// Saves every loaded chunk that was edited (the rest can just be generated again)
+    pub fn save_all(&self, dir: &Path) -> io::Result<()> {
+        region::save_chunks(dir, self.edited.iter().filter_map(|pos| self.chunks.get(pos).map(|chunk| (*pos, chunk))))
+    }
+
+    /// Roughly how much memory the loaded chunks and their light take up
+    pub fn size_bytes(&self) -> usize {
+        self.chunks.values().map(ChunkStorage::size_bytes).sum::<usize>()
+            + self.light.values().map(ChunkLight::size_bytes).sum::<usize>()
+    }
+
+    /// Copies the blocks of the neighbor at `offset` that are in a chunk's padding (a slab, a row or a corner), if both are loaded
+    fn copy_padding(&mut self, pos: ChunkPos, offset: [i32; 3]) {
+        let neighbor = [pos[0] + offset[0], pos[1] + offset[1], pos[2] + offset[2]];
+        let (data, source) = match (self.chunks.get(&pos), self.chunks.get(&neighbor)) {
+            (Some(data), Some(source)) => (data, source),
+            _ => return
+        };
+        if let (ChunkStorage::Uniform(a), ChunkStorage::Uniform(b)) = (data, source) {
+            if a == b { return }
+        }
+
+        let mut changes = vec![];
+        for z in padding_range(offset[2]) {
+            for y in padding_range(offset[1]) {
+                for x in padding_range(offset[0]) {
+                    let i = ChunkShape::linearize([x, y, z]) as usize;
+                    let block = source.get(ChunkShape::linearize(neighbor_local([x, y, z], offset).map(|c| c as u32)) as usize);
+                    if block != data.get(i) {
+                        changes.push((i, block));
+                    }
+                }
+            }
+        }
+        if changes.is_empty() { return }
+
+        let data = self.chunks.get_mut(&pos).unwrap();
+        for (i, block) in changes {
+            data.set(i, block);
+        }
+        self.dirty.insert(pos);
+    }
+}
+
+/// All 26 chunks touching a chunk, including diagonally
+pub const NEIGHBOR_OFFSETS: [[i32; 3]; 26] = {
+    let mut offsets = [[0; 3]; 26];
+    let mut i = 0;
+    let mut n = 0;
+    while i < 27 {
+        let offset = [i % 3 - 1, i / 3 % 3 - 1, i / 9 - 1];
+        if !(offset[0] == 0 && offset[1] == 0 && offset[2] == 0) {
+            offsets[n] = offset;
+            n += 1;
+        }
+        i += 1;
+    }
+    offsets
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use generator::SineHills;
+
+    #[test]
+    fn world_to_local_rounds_down() {
+        assert_eq!(world_to_local([0, 15, 16]), ([0, 0, 1], [1, 16, 1]));
+        assert_eq!(world_to_local([-1, -1, -1]), ([-1, -1, -1], [16, 16, 16]));
+        assert_eq!(world_to_local([-16, -16, -16]), ([-1, -1, -1], [1, 1, 1]));
+        assert_eq!(world_to_local([-17, -17, -17]), ([-2, -2, -2], [16, 16, 16]));
+
+        for world in [[-1, -16, -17], [5, -33, 40]] {
+            let (chunk, local) = world_to_local(world);
+            assert_eq!(local_to_world(chunk, local), world);
+        }
+    }
+
+    #[test]
+    fn set_block_updates_neighbors_padding() {
+        let mut world = TerrainState::new(Box::new(SineHills));
+        let mut chunks = vec![];
+        for x in -2..=0 {
+            for y in -2..=0 {
+                for z in -2..=0 {
+                    world.set_chunk([x, y, z], |_| Block::AIR);
+                    chunks.push([x, y, z]);
+                }
+            }
+        }
+        world.take_dirty();
+
+        // The lowest corner of chunk [-1, -1, -1], which is in the padding of the 7 chunks below it (including diagonally)
+        assert!(world.set_block([-16, -16, -16], Block::STONE));
+        let copies = [
+            ([-1, -1, -1], [1, 1, 1]),
+            ([-2, -1, -1], [17, 1, 1]),
+            ([-1, -2, -1], [1, 17, 1]),
+            ([-1, -1, -2], [1, 1, 17]),
+            ([-2, -2, -1], [17, 17, 1]),
+            ([-2, -1, -2], [17, 1, 17]),
+            ([-1, -2, -2], [1, 17, 17]),
+            ([-2, -2, -2], [17, 17, 17]),
+        ];
+        for (chunk, local) in copies {
+            assert_eq!(world.chunks[&chunk].get(ChunkShape::linearize(local) as usize), Block::STONE, "{:?} at {:?}", chunk, local);
+        }
+        assert_eq!(world.take_dirty(), copies.iter().map(|(chunk, _)| *chunk).collect());
+
+        // Every chunk's padding still matches its neighbors
+        for pos in chunks {
+            for i in 0..ChunkShape::SIZE {
+                let local = ChunkShape::delinearize(i);
+                if let Some(block) = world.get_block(local_to_world(pos, local)) {
+                    assert_eq!(world.chunks[&pos].get(i as usize), block, "{:?} at {:?}", pos, local);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn set_block_needs_a_loaded_chunk() {
+        let mut world = TerrainState::new(Box::new(SineHills));
+        world.set_chunk([0, 0, 0], |_| Block::AIR);
+        assert!(!world.set_block([-1, 0, 0], Block::STONE));
+        assert_eq!(world.get_block([-1, 0, 0]), None);
+        assert!(!world.chunks.contains_key(&[-1, 0, 0]));
+        assert_eq!(world.chunks[&[0, 0, 0]].get(ChunkShape::linearize([0, 1, 1]) as usize), Block::AIR);
+    }
+}
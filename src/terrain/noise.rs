@@ -0,0 +1,129 @@
+//! Seeded Perlin noise, and fractal (fBm) noise made by stacking it
+
+/// Classic Perlin gradient noise. Output is roughly in -1..1.
+pub struct Perlin {
+    /// A shuffled 0..256, twice over so lookups don't need to wrap
+    perm: [u8; 512]
+}
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        // xorshift64*, which is plenty random for shuffling a table (0 is a fixed point, so avoid it)
+        let mut state = seed ^ 0x9E37_79B9_7F4A_7C15;
+        if state == 0 { state = 1 }
+        let mut next = move || {
+            state ^= state >> 12;
+            state ^= state << 25;
+            state ^= state >> 27;
+            state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+        };
+
+        let mut table = [0u8; 256];
+        for (i, v) in table.iter_mut().enumerate() { *v = i as u8 }
+        for i in (1..256).rev() {
+            table.swap(i, (next() % (i as u64 + 1)) as usize);
+        }
+
+        let mut perm = [0u8; 512];
+        for (i, p) in perm.iter_mut().enumerate() { *p = table[i & 255] }
+        Self { perm }
+    }
+
+    fn hash(&self, x: i32, y: i32, z: i32) -> u8 {
+        let p = |i: i32| self.perm[(i & 255) as usize] as i32;
+        self.perm[(p(p(x) + y) + z) as usize & 511]
+    }
+
+    pub fn get2(&self, x: f64, y: f64) -> f64 {
+        // The 3D noise sampled on an integer z plane is just 2D noise
+        self.get3(x, y, 0.)
+    }
+
+    pub fn get3(&self, x: f64, y: f64, z: f64) -> f64 {
+        let (xi, yi, zi) = (x.floor() as i32, y.floor() as i32, z.floor() as i32);
+        let (xf, yf, zf) = (x - x.floor(), y - y.floor(), z - z.floor());
+        let (u, v, w) = (fade(xf), fade(yf), fade(zf));
+
+        let corner = |dx: i32, dy: i32, dz: i32| {
+            grad(self.hash(xi + dx, yi + dy, zi + dz), xf - dx as f64, yf - dy as f64, zf - dz as f64)
+        };
+
+        lerp(w,
+            lerp(v,
+                lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+                lerp(u, corner(0, 1, 0), corner(1, 1, 0))),
+            lerp(v,
+                lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+                lerp(u, corner(0, 1, 1), corner(1, 1, 1))))
+    }
+}
+
+/// Several octaves of Perlin noise, each one smaller and weaker than the last
+pub struct Fbm {
+    octaves: Vec<Perlin>,
+    /// Size of the biggest features, in blocks
+    pub scale: f64,
+    /// How much smaller each octave is
+    pub lacunarity: f64,
+    /// How much weaker each octave is
+    pub persistence: f64,
+}
+impl Fbm {
+    pub fn new(seed: u64, octaves: usize, scale: f64) -> Self {
+        Self {
+            octaves: (0..octaves as u64).map(|i| Perlin::new(seed.wrapping_add(i.wrapping_mul(0x5851_F42D)))).collect(),
+            scale,
+            lacunarity: 2.,
+            persistence: 0.5,
+        }
+    }
+
+    /// Roughly -1..1, no matter how many octaves
+    pub fn get2(&self, x: f64, y: f64) -> f64 {
+        self.sum(|noise, freq| noise.get2(x * freq, y * freq))
+    }
+
+    /// Roughly -1..1, no matter how many octaves
+    pub fn get3(&self, x: f64, y: f64, z: f64) -> f64 {
+        self.sum(|noise, freq| noise.get3(x * freq, y * freq, z * freq))
+    }
+
+    fn sum<F: Fn(&Perlin, f64) -> f64>(&self, sample: F) -> f64 {
+        let mut freq = 1. / self.scale;
+        let mut amp = 1.;
+        let mut total = 0.;
+        let mut max = 0.;
+        for octave in self.octaves.iter() {
+            total += sample(octave, freq) * amp;
+            max += amp;
+            freq *= self.lacunarity;
+            amp *= self.persistence;
+        }
+        total / max
+    }
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+/// Dot product with one of the 12 cube-edge gradients, picked by the hash
+fn grad(hash: u8, x: f64, y: f64, z: f64) -> f64 {
+    match hash & 15 {
+        0 | 12 => x + y,
+        1 | 14 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x + z,
+        5 => -x + z,
+        6 => x - z,
+        7 => -x - z,
+        8 => y + z,
+        9 | 13 => -y + z,
+        10 => y - z,
+        _ => -y - z,
+    }
+}
@@ -0,0 +1,152 @@
+use block_mesh::Voxel;
+use super::{Block, TerrainState};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    /// The block that was hit
+    pub block: [i32; 3],
+    /// Which side of it was hit, pointing out of the block (all 0 if the ray started inside it)
+    pub normal: [i32; 3],
+    /// How far from the ray's origin it entered the block
+    pub distance: f32,
+}
+
+impl TerrainState {
+    /// Finds the first visible block along a ray, stepping through every block the ray passes
+    /// (Amanatides & Woo's voxel traversal). Block [x, y, z] fills the cube from (x, y, z) to (x+1, y+1, z+1).
+    /// Chunks that aren't loaded count as empty.
+    pub fn raycast(&self, origin: [f32; 3], dir: [f32; 3], max_distance: f32) -> Option<RayHit> {
+        let len = (dir[0] * dir[0] + dir[1] * dir[1] + dir[2] * dir[2]).sqrt();
+        if len == 0. { return None }
+        let dir = dir.map(|d| d / len);
+
+        let mut block = origin.map(|c| c.floor() as i32);
+        let step = dir.map(|d| if d > 0. { 1 } else if d < 0. { -1 } else { 0 });
+        // How far along the ray it is to cross one whole block on each axis
+        let delta = dir.map(|d| if d == 0. { f32::INFINITY } else { 1. / d.abs() });
+        // How far along the ray the next block boundary on each axis is
+        let mut next = [0, 1, 2].map(|axis| {
+            let to_edge = if step[axis] > 0 {
+                block[axis] as f32 + 1. - origin[axis]
+            } else {
+                origin[axis] - block[axis] as f32
+            };
+            if step[axis] == 0 { f32::INFINITY } else { to_edge * delta[axis] }
+        });
+
+        let mut normal = [0; 3];
+        let mut distance = 0.;
+        while distance <= max_distance {
+            if let Some(hit) = self.get_block(block) {
+                if !hit.is_empty() {
+                    return Some(RayHit { block, normal, distance });
+                }
+            }
+
+            // Step into the next block on whichever axis has the nearest boundary
+            let axis = if next[0] < next[1] {
+                if next[0] < next[2] { 0 } else { 2 }
+            } else if next[1] < next[2] { 1 } else { 2 };
+
+            distance = next[axis];
+            next[axis] += delta[axis];
+            block[axis] += step[axis];
+            normal = [0; 3];
+            normal[axis] = -step[axis];
+        }
+        None
+    }
+}
+
+impl TerrainState {
+    /// Removes the block a ray hit, returning what it was
+    pub fn break_block(&mut self, hit: &RayHit) -> Option<Block> {
+        let old = self.get_block(hit.block)?;
+        self.set_block(hit.block, Block::AIR);
+        Some(old)
+    }
+
+    /// Puts a block against the face a ray hit.
+    /// Returns false if it couldn't, because the ray started inside a block, there's already something there,
+    /// or that spot's chunk isn't loaded.
+    pub fn place_block(&mut self, hit: &RayHit, block: Block) -> bool {
+        if hit.normal == [0; 3] { return false }
+
+        let pos = [0, 1, 2].map(|axis| hit.block[axis] + hit.normal[axis]);
+        match self.get_block(pos) {
+            Some(existing) if !existing.ty().visible => self.set_block(pos, block),
+            _ => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::generator::SineHills;
+
+    /// Empty chunks around the origin (blocks -16..16 on every axis), with `blocks` set to stone
+    fn world_with(blocks: &[[i32; 3]]) -> TerrainState {
+        let mut world = TerrainState::new(Box::new(SineHills));
+        for x in -1..=0 {
+            for y in -1..=0 {
+                for z in -1..=0 {
+                    world.set_chunk([x, y, z], |_| Block::AIR);
+                }
+            }
+        }
+        for &pos in blocks {
+            assert!(world.set_block(pos, Block::STONE));
+        }
+        world
+    }
+
+    fn assert_hit(hit: Option<RayHit>, block: [i32; 3], normal: [i32; 3], distance: f32) {
+        let hit = hit.expect("the ray should have hit something");
+        assert_eq!(hit.block, block);
+        assert_eq!(hit.normal, normal);
+        assert!((hit.distance - distance).abs() < 1e-5, "distance {} should be {}", hit.distance, distance);
+    }
+
+    #[test]
+    fn hits_each_face() {
+        let world = world_with(&[[3, 3, 3]]);
+        for axis in 0..3 {
+            for side in [-1, 1] {
+                // 4 blocks out from the block's center on one side, looking back at it
+                let mut origin = [3.5; 3];
+                origin[axis] += 4. * side as f32;
+                let mut dir = [0.; 3];
+                dir[axis] = -side as f32;
+                let mut normal = [0; 3];
+                normal[axis] = side;
+
+                assert_hit(world.raycast(origin, dir, 10.), [3, 3, 3], normal, 3.5);
+            }
+        }
+    }
+
+    #[test]
+    fn negative_coordinates() {
+        let world = world_with(&[[-5, -2, -7]]);
+        assert_hit(world.raycast([-0.5, -1.5, -6.5], [-1., 0., 0.], 10.), [-5, -2, -7], [1, 0, 0], 3.5);
+        assert_hit(world.raycast([-4.5, -10.5, -6.5], [0., 1., 0.], 10.), [-5, -2, -7], [0, -1, 0], 8.5);
+        // Diagonally, crossing from positive to negative on the way. It gets inside the block's x and z before its y, so it comes in the top.
+        assert_hit(world.raycast([0.25, 3.75, -1.5], [-1., -1., -1.], 20.), [-5, -2, -7], [0, 1, 0], 3f32.sqrt() * 4.75);
+    }
+
+    #[test]
+    fn starts_inside_a_block() {
+        let world = world_with(&[[3, 3, 3]]);
+        assert_hit(world.raycast([3.5, 3.5, 3.5], [1., 0., 0.], 10.), [3, 3, 3], [0; 3], 0.);
+    }
+
+    #[test]
+    fn max_distance() {
+        let world = world_with(&[[3, 3, 3]]);
+        assert!(world.raycast([3.5, 3.5, -0.5], [0., 0., 1.], 3.).is_none());
+        assert_hit(world.raycast([3.5, 3.5, -0.5], [0., 0., 1.], 3.5), [3, 3, 3], [0, 0, -1], 3.5);
+        // Missing is missing, however far it goes
+        assert!(world.raycast([3.5, 3.5, -0.5], [0., 1., 0.], 100.).is_none());
+    }
+}
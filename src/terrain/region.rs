@@ -0,0 +1,305 @@
+//! Saving chunks to disk.
+//!
+//! Chunks are grouped into region files of REGION_SIZE x REGION_SIZE chunk columns (one chunk tall),
+//! so a world isn't thousands of tiny files.
+//!
+//! File layout (all numbers little-endian):
+//! - MAGIC, then the format VERSION as a u32
+//! - A table with an (offset, length) pair of u32s for each chunk in the region, zero length meaning it isn't saved
+//! - The chunks, each run-length encoded as (run length: u16, block id: u16) pairs
+//!
+//! Saving and loading chunks are functions here (save_chunk(), save_chunks() and load_chunk()) rather than TerrainState methods,
+//! so the chunk workers can load without the world. TerrainState saves its edited chunks with them when they're unloaded, and in save_all().
+
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use super::{Block, ChunkPos, ChunkStorage, ChunkShape};
+use block_mesh::ndshape::ConstShape;
+
+pub const REGION_SIZE: i32 = 32;
+const CHUNKS_PER_REGION: usize = (REGION_SIZE * REGION_SIZE) as usize;
+
+const MAGIC: &[u8; 4] = b"CCRG";
+pub const VERSION: u32 = 1;
+const HEADER_LEN: usize = 4 + 4 + CHUNKS_PER_REGION * 8;
+
+pub type RegionPos = [i32; 3];
+
+/// Which region file a chunk goes in, and which slot of its table
+pub fn region_of(chunk: ChunkPos) -> (RegionPos, usize) {
+    let local_x = chunk[0].rem_euclid(REGION_SIZE);
+    let local_z = chunk[2].rem_euclid(REGION_SIZE);
+    (
+        [chunk[0].div_euclid(REGION_SIZE), chunk[1], chunk[2].div_euclid(REGION_SIZE)],
+        (local_x + local_z * REGION_SIZE) as usize
+    )
+}
+
+pub fn region_path(dir: &Path, region: RegionPos) -> PathBuf {
+    dir.join(format!("r.{}.{}.{}.region", region[0], region[1], region[2]))
+}
+
+/// Loads one chunk from its region file in `dir`, or None if it was never saved.
+/// Doesn't need a TerrainState, so it can run on another thread.
+pub fn load_chunk(dir: &Path, pos: ChunkPos) -> io::Result<Option<ChunkStorage>> {
+    let (region_pos, slot) = region_of(pos);
+    Region::read(&region_path(dir, region_pos))?.get(slot)
+}
+
+/// Saves one chunk into its region file in `dir`
+pub fn save_chunk(dir: &Path, pos: ChunkPos, chunk: &ChunkStorage) -> io::Result<()> {
+    save_chunks(dir, [(pos, chunk)])
+}
+
+/// Saves chunks into their region files in `dir`, reading and writing each region file once
+pub fn save_chunks<'a>(dir: &Path, chunks: impl IntoIterator<Item = (ChunkPos, &'a ChunkStorage)>) -> io::Result<()> {
+    let mut by_region: HashMap<RegionPos, Vec<(usize, &ChunkStorage)>> = HashMap::new();
+    for (pos, chunk) in chunks {
+        let (region_pos, slot) = region_of(pos);
+        by_region.entry(region_pos).or_default().push((slot, chunk));
+    }
+
+    std::fs::create_dir_all(dir)?;
+    for (region_pos, chunks) in by_region {
+        let path = region_path(dir, region_pos);
+        let mut region = Region::read(&path)?;
+        for (slot, chunk) in chunks {
+            region.set(slot, chunk);
+        }
+        region.write(&path)?;
+    }
+    Ok(())
+}
+
+/// A whole region file in memory. They're small enough to just read and rewrite completely.
+pub struct Region {
+    chunks: Vec<Option<Vec<u8>>>
+}
+impl Region {
+    pub fn new() -> Self {
+        Self { chunks: vec![None; CHUNKS_PER_REGION] }
+    }
+
+    /// Reads a region file, or makes an empty region if there isn't one yet
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::new()),
+            Err(e) => return Err(e)
+        };
+
+        if bytes.len() < HEADER_LEN || &bytes[0..4] != MAGIC {
+            return Err(invalid(format!("{:?} isn't a region file", path)));
+        }
+        let version = read_u32(&bytes, 4);
+        if version != VERSION {
+            return Err(invalid(format!("{:?} is region format version {}, expected {}", path, version, VERSION)));
+        }
+
+        let mut region = Self::new();
+        for (i, slot) in region.chunks.iter_mut().enumerate() {
+            let offset = read_u32(&bytes, 8 + i * 8) as usize;
+            let len = read_u32(&bytes, 8 + i * 8 + 4) as usize;
+            if len == 0 { continue }
+
+            let data = bytes.get(offset..offset + len)
+                .ok_or_else(|| invalid(format!("chunk {} of {:?} is past the end of the file", i, path)))?;
+            *slot = Some(data.to_vec());
+        }
+        Ok(region)
+    }
+
+    /// Writes to a temporary file first, so a crash while saving doesn't corrupt the old region
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+
+        let mut offset = HEADER_LEN;
+        for chunk in self.chunks.iter() {
+            let len = chunk.as_ref().map_or(0, |data| data.len());
+            bytes.extend_from_slice(&(offset as u32).to_le_bytes());
+            bytes.extend_from_slice(&(len as u32).to_le_bytes());
+            offset += len;
+        }
+        for data in self.chunks.iter().flatten() {
+            bytes.extend_from_slice(data);
+        }
+
+        let tmp = path.with_extension("region.tmp");
+        std::fs::write(&tmp, &bytes)?;
+        std::fs::rename(&tmp, path)
+    }
+
+    pub fn get(&self, slot: usize) -> io::Result<Option<ChunkStorage>> {
+        self.chunks[slot].as_ref().map(|data| decode_chunk(data)).transpose()
+    }
+
+    pub fn set(&mut self, slot: usize, chunk: &ChunkStorage) {
+        self.chunks[slot] = Some(encode_chunk(chunk));
+    }
+}
+
+fn encode_chunk(chunk: &ChunkStorage) -> Vec<u8> {
+    let mut data = vec![];
+    let mut push_run = |len: u16, block: Block| {
+        data.extend_from_slice(&len.to_le_bytes());
+        data.extend_from_slice(&block.id.to_le_bytes());
+    };
+
+    let mut run: Option<(u16, Block)> = None;
+    for i in 0..ChunkShape::SIZE as usize {
+        let block = chunk.get(i);
+        run = match run {
+            Some((len, prev)) if prev == block && len < u16::MAX => Some((len + 1, prev)),
+            Some((len, prev)) => { push_run(len, prev); Some((1, block)) },
+            None => Some((1, block))
+        };
+    }
+    if let Some((len, prev)) = run {
+        push_run(len, prev);
+    }
+    data
+}
+
+fn decode_chunk(data: &[u8]) -> io::Result<ChunkStorage> {
+    if !data.len().is_multiple_of(4) {
+        return Err(invalid(format!("chunk is {} bytes, which isn't a whole number of runs", data.len())));
+    }
+    let mut blocks = Vec::with_capacity(ChunkShape::SIZE as usize);
+    for pair in data.chunks_exact(4) {
+        let len = u16::from_le_bytes([pair[0], pair[1]]);
+        let id = u16::from_le_bytes([pair[2], pair[3]]);
+        if id as usize >= super::BLOCK_TYPES.len() {
+            return Err(invalid(format!("unknown block id {}", id)));
+        }
+        blocks.extend(std::iter::repeat_n(Block { id }, len as usize));
+    }
+
+    if blocks.len() != ChunkShape::SIZE as usize {
+        return Err(invalid(format!("chunk has {} blocks, expected {}", blocks.len(), ChunkShape::SIZE)));
+    }
+    Ok(ChunkStorage::from_blocks(&blocks))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_same(a: &ChunkStorage, b: &ChunkStorage) {
+        assert_eq!(&a.to_blocks()[..], &b.to_blocks()[..]);
+    }
+
+    fn paletted() -> ChunkStorage {
+        let blocks = (0..ChunkShape::SIZE as usize)
+            .map(|i| match i % 13 {
+                0..=5 => Block::STONE,
+                6 => Block::GLASS,
+                7..=9 => Block::DIRT,
+                _ => Block::AIR,
+            })
+            .collect::<Vec<_>>();
+        ChunkStorage::from_blocks(&blocks)
+    }
+
+    fn run(len: u16, block: Block) -> Vec<u8> {
+        [len.to_le_bytes(), block.id.to_le_bytes()].concat()
+    }
+
+    #[test]
+    fn chunk_round_trip() {
+        for chunk in [ChunkStorage::from_blocks(&[Block::DIRT; ChunkShape::SIZE as usize]), paletted()] {
+            assert_same(&decode_chunk(&encode_chunk(&chunk)).unwrap(), &chunk);
+        }
+    }
+
+    #[test]
+    fn long_runs() {
+        // A whole chunk is shorter than u16::MAX, so a uniform one is a single run
+        let stone = ChunkStorage::from_blocks(&[Block::STONE; ChunkShape::SIZE as usize]);
+        assert_eq!(encode_chunk(&stone), run(ChunkShape::SIZE as u16, Block::STONE));
+
+        // Runs that were split up (like ones longer than u16::MAX would be) join back together
+        let half = ChunkShape::SIZE as u16 / 2;
+        let split = [run(half, Block::STONE), run(1, Block::STONE), run(ChunkShape::SIZE as u16 - half - 1, Block::STONE)].concat();
+        assert_same(&decode_chunk(&split).unwrap(), &stone);
+    }
+
+    #[test]
+    fn rejects_bad_chunks() {
+        let good = encode_chunk(&paletted());
+        let cases = [
+            // Not a whole number of runs
+            [&good[..], &[0]].concat(),
+            good[..good.len() - 1].to_vec(),
+            // Too short and too long
+            run(10, Block::AIR),
+            [&good[..], &run(1, Block::AIR)[..]].concat(),
+            // A block that doesn't exist
+            run(ChunkShape::SIZE as u16, Block { id: crate::terrain::BLOCK_TYPES.len() as u16 }),
+        ];
+        for data in cases {
+            assert_eq!(decode_chunk(&data).unwrap_err().kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn region_round_trip() {
+        let dir = std::env::temp_dir().join(format!("crispycraft-region-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = region_path(&dir, [0, 0, 0]);
+
+        // Nothing saved yet
+        assert!(Region::read(&path).unwrap().get(3).unwrap().is_none());
+
+        let uniform = ChunkStorage::from_blocks(&[Block::GRASS; ChunkShape::SIZE as usize]);
+        let mut region = Region::new();
+        region.set(3, &paletted());
+        region.set(CHUNKS_PER_REGION - 1, &uniform);
+        region.write(&path).unwrap();
+
+        let read = Region::read(&path).unwrap();
+        assert_same(&read.get(3).unwrap().unwrap(), &paletted());
+        assert_same(&read.get(CHUNKS_PER_REGION - 1).unwrap().unwrap(), &uniform);
+        for slot in (0..CHUNKS_PER_REGION).filter(|&slot| slot != 3 && slot != CHUNKS_PER_REGION - 1) {
+            assert!(read.get(slot).unwrap().is_none());
+        }
+
+        // Garbage isn't a region
+        std::fs::write(&path, b"not a region").unwrap();
+        assert_eq!(Region::read(&path).err().unwrap().kind(), ErrorKind::InvalidData);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_and_load_chunks() {
+        let dir = std::env::temp_dir().join(format!("crispycraft-save-test-{}", std::process::id()));
+        let uniform = ChunkStorage::from_blocks(&[Block::GRASS; ChunkShape::SIZE as usize]);
+
+        // In different regions, one of them at negative coordinates
+        save_chunk(&dir, [-1, 0, -33], &paletted()).unwrap();
+        save_chunks(&dir, [([5, 2, 5], &uniform), ([6, 2, 5], &paletted())]).unwrap();
+
+        assert_same(&load_chunk(&dir, [-1, 0, -33]).unwrap().unwrap(), &paletted());
+        assert_same(&load_chunk(&dir, [5, 2, 5]).unwrap().unwrap(), &uniform);
+        assert_same(&load_chunk(&dir, [6, 2, 5]).unwrap().unwrap(), &paletted());
+        // Saving into a region keeps what was already in it
+        save_chunk(&dir, [5, 2, 5], &paletted()).unwrap();
+        assert_same(&load_chunk(&dir, [6, 2, 5]).unwrap().unwrap(), &paletted());
+        assert!(load_chunk(&dir, [7, 2, 5]).unwrap().is_none());
+        assert!(load_chunk(&dir, [5, 3, 5]).unwrap().is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
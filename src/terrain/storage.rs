@@ -0,0 +1,233 @@
+use block_mesh::ndshape::ConstShape;
+use super::{Block, ChunkData, ChunkShape};
+
+const VOXELS: usize = ChunkShape::SIZE as usize;
+
+/// A chunk's blocks, compressed.
+/// Most chunks are all air or all stone, and the rest only have a few kinds of block,
+/// so each block is stored as a few bits pointing into a list of the kinds in the chunk.
+#[derive(Clone, Debug)]
+pub enum ChunkStorage {
+    /// Every block is the same
+    Uniform(Block),
+    Paletted {
+        /// Every kind of block that's been in the chunk
+        palette: Vec<Block>,
+        /// Bits per index into the palette
+        bits: u32,
+        /// The indices, packed into words (an index never crosses two words)
+        words: Vec<u64>,
+    },
+}
+impl ChunkStorage {
+    pub fn from_blocks(blocks: &[Block]) -> Self {
+        let mut palette = vec![];
+        for block in blocks {
+            if !palette.contains(block) {
+                palette.push(*block);
+            }
+        }
+        if palette.len() == 1 {
+            return Self::Uniform(palette[0]);
+        }
+
+        let bits = bits_for(palette.len());
+        let mut storage = Self::Paletted {
+            words: vec![0; words_for(bits)],
+            palette,
+            bits,
+        };
+        for (i, block) in blocks.iter().enumerate() {
+            storage.set(i, *block);
+        }
+        storage
+    }
+
+    pub fn get(&self, i: usize) -> Block {
+        match self {
+            Self::Uniform(block) => *block,
+            Self::Paletted { palette, bits, words } => palette[read_index(words, *bits, i)],
+        }
+    }
+
+    pub fn set(&mut self, i: usize, block: Block) {
+        if let Self::Uniform(fill) = *self {
+            if fill == block { return }
+
+            // Switch to a palette with the old block at index 0, which is what the words start as
+            *self = Self::Paletted {
+                palette: vec![fill],
+                bits: 1,
+                words: vec![0; words_for(1)],
+            };
+        }
+
+        if let Self::Paletted { palette, bits, words } = self {
+            let index = match palette.iter().position(|b| *b == block) {
+                Some(index) => index,
+                None => {
+                    palette.push(block);
+                    // Repack everything when the indices don't fit anymore
+                    let new_bits = bits_for(palette.len());
+                    if new_bits != *bits {
+                        let mut new_words = vec![0; words_for(new_bits)];
+                        for j in 0..VOXELS {
+                            write_index(&mut new_words, new_bits, j, read_index(words, *bits, j));
+                        }
+                        *words = new_words;
+                        *bits = new_bits;
+                    }
+                    palette.len() - 1
+                }
+            };
+            write_index(words, *bits, i, index);
+        }
+    }
+
+    /// Unpacks the chunk into a flat array, which is what greedy_quads() needs
+    pub fn to_blocks(&self) -> ChunkData {
+        match self {
+            Self::Uniform(block) => [*block; VOXELS],
+            Self::Paletted { palette, bits, words } => {
+                // A word at a time, instead of working out where each index is like get() does
+                let per_word = (64 / bits) as usize;
+                let mask = (1 << bits) - 1;
+                let mut blocks = [Block::AIR; VOXELS];
+                for (run, word) in blocks.chunks_mut(per_word).zip(words) {
+                    for (j, block) in run.iter_mut().enumerate() {
+                        *block = palette[((word >> (j as u32 * bits)) & mask) as usize];
+                    }
+                }
+                blocks
+            }
+        }
+    }
+
+    /// Roughly how much memory this chunk takes up
+    pub fn size_bytes(&self) -> usize {
+        std::mem::size_of::<Self>() + match self {
+            Self::Uniform(_) => 0,
+            Self::Paletted { palette, words, .. } => {
+                palette.capacity() * std::mem::size_of::<Block>() + words.capacity() * 8
+            }
+        }
+    }
+}
+
+fn bits_for(palette_len: usize) -> u32 {
+    // At least 1 bit, even though a palette of 1 would need 0
+    (usize::BITS - (palette_len.max(2) - 1).leading_zeros()).max(1)
+}
+
+fn words_for(bits: u32) -> usize {
+    let per_word = (64 / bits) as usize;
+    VOXELS.div_ceil(per_word)
+}
+
+fn read_index(words: &[u64], bits: u32, i: usize) -> usize {
+    let per_word = (64 / bits) as usize;
+    let shift = (i % per_word) as u32 * bits;
+    ((words[i / per_word] >> shift) & ((1 << bits) - 1)) as usize
+}
+
+fn write_index(words: &mut [u64], bits: u32, i: usize, index: usize) {
+    let per_word = (64 / bits) as usize;
+    let shift = (i % per_word) as u32 * bits;
+    let mask = ((1u64 << bits) - 1) << shift;
+    let word = &mut words[i / per_word];
+    *word = (*word & !mask) | ((index as u64) << shift);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [Block; 6] = [Block::AIR, Block::STONE, Block::DIRT, Block::GRASS, Block::GLASS, Block::LAMP];
+
+    /// Some blocks using the first `kinds` kinds, mixed up so neighboring indices are different
+    fn pattern(kinds: usize) -> Vec<Block> {
+        (0..VOXELS).map(|i| KINDS[(i * 7 + i / 11) % kinds]).collect()
+    }
+
+    fn assert_blocks(storage: &ChunkStorage, expected: &[Block]) {
+        for (i, block) in expected.iter().enumerate() {
+            assert_eq!(storage.get(i), *block, "block {}", i);
+        }
+        assert_eq!(&storage.to_blocks()[..], expected);
+    }
+
+    #[test]
+    fn uniform_round_trip() {
+        let blocks = vec![Block::STONE; VOXELS];
+        let storage = ChunkStorage::from_blocks(&blocks);
+        assert!(matches!(storage, ChunkStorage::Uniform(Block::STONE)));
+        assert_blocks(&storage, &blocks);
+    }
+
+    #[test]
+    fn paletted_round_trip() {
+        for kinds in 2..=KINDS.len() {
+            let blocks = pattern(kinds);
+            let storage = ChunkStorage::from_blocks(&blocks);
+            match &storage {
+                ChunkStorage::Paletted { palette, bits, .. } => {
+                    assert_eq!(palette.len(), kinds);
+                    assert_eq!(*bits, bits_for(kinds));
+                },
+                ChunkStorage::Uniform(_) => panic!("{} kinds of block should need a palette", kinds),
+            }
+            assert_blocks(&storage, &blocks);
+        }
+    }
+
+    #[test]
+    fn set_switches_uniform_to_paletted() {
+        let mut blocks = vec![Block::AIR; VOXELS];
+        let mut storage = ChunkStorage::from_blocks(&blocks);
+
+        // The same block again doesn't need a palette
+        storage.set(10, Block::AIR);
+        assert!(matches!(storage, ChunkStorage::Uniform(Block::AIR)));
+
+        storage.set(10, Block::STONE);
+        blocks[10] = Block::STONE;
+        assert!(matches!(storage, ChunkStorage::Paletted { bits: 1, .. }));
+        assert_blocks(&storage, &blocks);
+    }
+
+    #[test]
+    fn set_repacks_when_the_palette_outgrows_the_bits() {
+        let mut blocks = pattern(2);
+        let mut storage = ChunkStorage::from_blocks(&blocks);
+
+        // 2 kinds fit in 1 bit, 3 and 4 in 2 bits, 5 and 6 in 3 bits
+        for (kinds, expected_bits) in [(3, 2), (4, 2), (5, 3), (6, 3)] {
+            let i = kinds * 100;
+            storage.set(i, KINDS[kinds - 1]);
+            blocks[i] = KINDS[kinds - 1];
+            match &storage {
+                ChunkStorage::Paletted { palette, bits, .. } => {
+                    assert_eq!(palette.len(), kinds);
+                    assert_eq!(*bits, expected_bits);
+                },
+                ChunkStorage::Uniform(_) => panic!("should still be paletted"),
+            }
+            assert_blocks(&storage, &blocks);
+        }
+
+        // Writing over blocks after a repack only changes those blocks
+        for i in (0..VOXELS).step_by(3) {
+            storage.set(i, Block::LAMP);
+            blocks[i] = Block::LAMP;
+        }
+        assert_blocks(&storage, &blocks);
+    }
+
+    #[test]
+    fn uniform_is_smaller() {
+        let uniform = ChunkStorage::from_blocks(&vec![Block::STONE; VOXELS]);
+        let paletted = ChunkStorage::from_blocks(&pattern(2));
+        assert!(uniform.size_bytes() < paletted.size_bytes());
+        assert!(paletted.size_bytes() >= words_for(1) * 8);
+    }
+}
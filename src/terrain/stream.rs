@@ -0,0 +1,114 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use super::{world_to_local, ChunkPos, ChunkStorage, TerrainState};
+
+/// Keeps the chunks around a point (the camera) loaded, and unloads the ones that are too far away
+pub struct ChunkStreamer {
+    /// How many chunks away from the center to load, horizontally
+    pub view_distance: i32,
+    /// How many chunks away from the center to load, vertically
+    pub vertical_distance: i32,
+    /// Most chunks to start loading in one update(), so walking into new terrain doesn't flood the workers
+    pub loads_per_update: usize,
+    /// Where chunks get saved to and loaded from
+    pub world_dir: PathBuf,
+
+    /// Chunks in range, nearest first. Some of them might not be loaded yet.
+    in_range: Vec<ChunkPos>,
+    center: Option<ChunkPos>,
+    /// Chunks that are being loaded or generated somewhere else
+    pending: HashSet<ChunkPos>,
+}
+impl ChunkStreamer {
+    pub fn new(world_dir: PathBuf, view_distance: i32, vertical_distance: i32) -> Self {
+        Self {
+            view_distance,
+            vertical_distance,
+            loads_per_update: 8,
+            world_dir,
+            in_range: vec![],
+            center: None,
+            pending: HashSet::new(),
+        }
+    }
+
+    /// The chunks that should be drawn (meshes that aren't ready yet should just be skipped)
+    pub fn in_range(&self) -> &[ChunkPos] {
+        &self.in_range
+    }
+
+    /// Loads chunks that are in range of `eye`, and unloads chunks that aren't.
+    /// Chunks that need loading are passed to `load` (which should read them from world_dir, or generate them if they were never saved),
+    /// and should come back through finish_loaded().
+    /// Returns the chunks that got unloaded, so their meshes can be freed.
+    pub fn update<L: FnMut(ChunkPos)>(&mut self, world: &mut TerrainState, eye: [f32; 3], mut load: L) -> Vec<ChunkPos> {
+        let (center, _) = world_to_local([eye[0].floor() as i32, eye[1].floor() as i32, eye[2].floor() as i32]);
+
+        let mut unloaded = vec![];
+        if self.center != Some(center) {
+            self.center = Some(center);
+            self.in_range = self.chunks_around(center);
+
+            // One chunk of leeway, so going back and forth over a chunk border doesn't keep reloading things
+            let far = world.chunks.keys()
+                .filter(|pos| !self.within(center, **pos, 1))
+                .copied()
+                .collect::<Vec<_>>();
+            for pos in far {
+                if let Err(e) = world.unload_chunk(&self.world_dir, pos) {
+                    eprintln!("Couldn't save chunk {:?}: {}", pos, e);
+                }
+                unloaded.push(pos);
+            }
+        }
+
+        let missing = self.in_range.iter()
+            .filter(|pos| !world.chunks.contains_key(*pos) && !self.pending.contains(*pos))
+            .take(self.loads_per_update)
+            .copied()
+            .collect::<Vec<_>>();
+        for pos in missing {
+            self.pending.insert(pos);
+            load(pos);
+        }
+
+        unloaded
+    }
+
+    /// Adds a chunk that was passed to `load` in update(), unless it's out of range now
+    /// (or got loaded some other way in the meantime)
+    pub fn finish_loaded(&mut self, world: &mut TerrainState, pos: ChunkPos, chunk: ChunkStorage) {
+        self.pending.remove(&pos);
+        let wanted = self.center.is_some_and(|center| self.within(center, pos, 1));
+        if wanted && !world.chunks.contains_key(&pos) {
+            world.insert_chunk(pos, chunk);
+        }
+    }
+
+    fn within(&self, center: ChunkPos, pos: ChunkPos, leeway: i32) -> bool {
+        let [dx, dy, dz] = [pos[0] - center[0], pos[1] - center[1], pos[2] - center[2]];
+        let radius = self.view_distance + leeway;
+        dx * dx + dz * dz <= radius * radius && dy.abs() <= self.vertical_distance + leeway
+    }
+
+    fn chunks_around(&self, center: ChunkPos) -> Vec<ChunkPos> {
+        let (h, v) = (self.view_distance, self.vertical_distance);
+        let mut chunks = vec![];
+        for x in -h..=h {
+            for y in -v..=v {
+                for z in -h..=h {
+                    let pos = [center[0] + x, center[1] + y, center[2] + z];
+                    if self.within(center, pos, 0) {
+                        chunks.push(pos);
+                    }
+                }
+            }
+        }
+        chunks.sort_by_key(|pos| {
+            let [dx, dy, dz] = [pos[0] - center[0], pos[1] - center[1], pos[2] - center[2]];
+            dx * dx + dy * dy + dz * dz
+        });
+        chunks
+    }
+}
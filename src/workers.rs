@@ -0,0 +1,113 @@
+//! Background threads for the slow parts of streaming terrain: loading or generating chunks, and meshing them.
+//! The main thread sends jobs in and picks up the results whenever it's ready for them.
+
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use block_mesh::GreedyQuadsBuffer;
+use block_mesh::ndshape::ConstShape;
+
+use crate::game::{self, CPUMesh, Vertex};
+use crate::terrain::{self, region, ChunkData, ChunkPos, ChunkShape, ChunkStorage, PaddedLight, TerrainGenerator};
+
+enum Job {
+    Load(ChunkPos),
+    Mesh(ChunkPos, u64, Box<ChunkData>, Box<PaddedLight>),
+}
+
+pub struct WorkerPool {
+    jobs: Sender<Job>,
+    chunks: Receiver<(ChunkPos, ChunkStorage)>,
+    meshes: Receiver<(ChunkPos, u64, CPUMesh<Vertex>)>,
+}
+impl WorkerPool {
+    /// Starts `threads` workers (at least 1), which load saved chunks from `world_dir`
+    pub fn new(threads: usize, generator: Arc<dyn TerrainGenerator>, world_dir: PathBuf) -> Self {
+        let (jobs, job_receiver) = channel::<Job>();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let (chunk_sender, chunks) = channel();
+        let (mesh_sender, meshes) = channel();
+
+        for i in 0..threads.max(1) {
+            let job_receiver = job_receiver.clone();
+            let chunk_sender = chunk_sender.clone();
+            let mesh_sender = mesh_sender.clone();
+            let generator = generator.clone();
+            let world_dir = world_dir.clone();
+
+            thread::Builder::new().name(format!("chunk worker {}", i)).spawn(move || {
+                // Each worker has its own, so they don't have to share
+                let mut stage_buffer = GreedyQuadsBuffer::new(ChunkShape::SIZE as usize);
+
+                loop {
+                    // The lock is only held while waiting for a job, not while doing it
+                    let job = match job_receiver.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        // The pool was dropped
+                        Err(_) => return
+                    };
+
+                    // Sending only fails if the pool was dropped, and then nobody cares about the result
+                    match job {
+                        Job::Load(pos) => {
+                            let chunk = match region::load_chunk(&world_dir, pos) {
+                                Ok(Some(chunk)) => chunk,
+                                Ok(None) => generate(generator.as_ref(), pos),
+                                Err(e) => {
+                                    eprintln!("Couldn't load chunk {:?}, generating it instead: {}", pos, e);
+                                    generate(generator.as_ref(), pos)
+                                }
+                            };
+                            let _ = chunk_sender.send((pos, chunk));
+                        },
+                        Job::Mesh(pos, version, data, light) => {
+                            let mesh = game::build_chunk_mesh(
+                                &mut stage_buffer, pos,
+                                &ChunkShape {}, &data[..], &light[..],
+                                terrain::SIZE, 1.
+                            );
+                            let _ = mesh_sender.send((pos, version, mesh));
+                        }
+                    }
+                }
+            }).expect("Couldn't start a chunk worker thread");
+        }
+
+        Self { jobs, chunks, meshes }
+    }
+
+    /// One worker per core, leaving one for the main thread
+    pub fn with_available_threads(generator: Arc<dyn TerrainGenerator>, world_dir: PathBuf) -> Self {
+        let cores = thread::available_parallelism().map(|n| n.get()).unwrap_or(2);
+        Self::new(cores.saturating_sub(1), generator, world_dir)
+    }
+
+    /// Loads a chunk if it was saved, otherwise generates the whole thing, padding included
+    /// (the padding can be fixed up from loaded neighbors later)
+    pub fn load(&self, pos: ChunkPos) {
+        self.jobs.send(Job::Load(pos)).expect("The chunk workers stopped");
+    }
+
+    /// Meshes a chunk with its light. `version` is handed back with the mesh, to tell it apart from other meshes of the same chunk.
+    pub fn mesh(&self, pos: ChunkPos, version: u64, data: Box<ChunkData>, light: Box<PaddedLight>) {
+        self.jobs.send(Job::Mesh(pos, version, data, light)).expect("The chunk workers stopped");
+    }
+
+    pub fn try_recv_chunk(&self) -> Option<(ChunkPos, ChunkStorage)> {
+        self.chunks.try_recv().ok()
+    }
+
+    pub fn try_recv_mesh(&self) -> Option<(ChunkPos, u64, CPUMesh<Vertex>)> {
+        self.meshes.try_recv().ok()
+    }
+}
+
+fn generate(generator: &dyn TerrainGenerator, pos: ChunkPos) -> ChunkStorage {
+    let mut data = Vec::with_capacity(ChunkShape::SIZE as usize);
+    for i in 0..ChunkShape::SIZE {
+        data.push(generator.block_at(terrain::local_to_world(pos, ChunkShape::delinearize(i))));
+    }
+    ChunkStorage::from_blocks(&data)
+}